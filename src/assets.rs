@@ -1,16 +1,23 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
 use bevy::prelude::*;
+use bevy::ecs::system::SystemParam;
 use bevy::reflect::TypePath;
-use bevy_asset_loader::prelude::*;
+use bevy_asset_loader::{dynamic_asset::DynamicAssets, prelude::*};
 use bevy_common_assets::ron::RonAssetPlugin;
 use bevy_egui::{egui::TextureId, EguiContexts};
-use bevy_kira_audio::AudioSource;
+use bevy_kira_audio::{Audio, AudioControl, AudioSource, PlayAudioCommand};
 use serde::Deserialize;
 
 use crate::{
     AppState,
     animation::Animation,
+    enemies::{boss::BossesConfig, spawner::WaveSchedule},
+    game_feel::GameFeelConfig,
+    pickups::DropTable,
+    upgrades::UpgradesConfig,
+    weapons::WeaponsConfig,
 };
 
 pub struct AssetsPlugin;
@@ -18,10 +25,18 @@ pub struct AssetsPlugin;
 impl Plugin for AssetsPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_plugins(RonAssetPlugin::<AudioConfig>::new(&["audio.ron"]))
+            .add_plugins((
+                RonAssetPlugin::<AudioConfig>::new(&["audio.ron"]),
+                RonAssetPlugin::<BossesConfig>::new(&["bosses.ron"]),
+                RonAssetPlugin::<DropTable>::new(&["drops.ron"]),
+                RonAssetPlugin::<GameFeelConfig>::new(&["feel.ron"]),
+                RonAssetPlugin::<UpgradesConfig>::new(&["upgrades.ron"]),
+                RonAssetPlugin::<WaveSchedule>::new(&["waves.ron"]),
+                RonAssetPlugin::<WeaponsConfig>::new(&["weapons.ron"]),
+            ))
             .add_loading_state(
                 LoadingState::new(AppState::Loading)
-                    .continue_to_state(AppState::InGame)
+                    .continue_to_state(AppState::MainMenu)
                     .with_dynamic_assets_file::<StandardDynamicAssetCollection>("audio/audio.assets.ron")
                     .load_collection::<GameAssets>()
                     .load_collection::<AudioAssets>()
@@ -30,7 +45,7 @@ impl Plugin for AssetsPlugin {
     }
 }
 
-#[derive(Resource, AssetCollection)]
+#[derive(Default, Resource, AssetCollection)]
 pub struct GameAssets {
     #[asset(path = "player.png")]
     pub player: Handle<Image>,
@@ -82,6 +97,23 @@ pub struct GameAssets {
     pub whole_heart: Handle<Image>,
     #[asset(path = "empty_heart.png")]
     pub empty_heart: Handle<Image>,
+    /// Made out of the whole and empty hearts once they're loaded.
+    pub half_heart: Handle<Image>,
+    #[asset(path = "heart_drop.png")]
+    pub heart_drop: Handle<Image>,
+
+    #[asset(path = "config.weapons.ron")]
+    pub weapons: Handle<WeaponsConfig>,
+    #[asset(path = "config.bosses.ron")]
+    pub bosses: Handle<BossesConfig>,
+    #[asset(path = "config.waves.ron")]
+    pub waves: Handle<WaveSchedule>,
+    #[asset(path = "config.drops.ron")]
+    pub drops: Handle<DropTable>,
+    #[asset(path = "config.upgrades.ron")]
+    pub upgrades: Handle<UpgradesConfig>,
+    #[asset(path = "config.feel.ron")]
+    pub feel: Handle<GameFeelConfig>,
 
     #[asset(path = "dice1.png")]
     pub dice1: Handle<Image>,
@@ -96,19 +128,6 @@ pub struct GameAssets {
     #[asset(path = "dice6.png")]
     pub dice6: Handle<Image>,
 
-    #[asset(path = "pistol.png")]
-    pub pistol: Handle<Image>,
-    #[asset(path = "ray_gun.png")]
-    pub ray_gun: Handle<Image>,
-    #[asset(path = "shotgun.png")]
-    pub shotgun: Handle<Image>,
-    #[asset(path = "boomerang.png")]
-    pub boomerang: Handle<Image>,
-    #[asset(path = "smg.png")]
-    pub smg: Handle<Image>,
-    #[asset(path = "grenade_launcher.png")]
-    pub grenade_launcher: Handle<Image>,
-
     pub egui_images: EguiImages,
 }
 
@@ -159,6 +178,7 @@ pub struct EnemyIndices {
     pub rat: usize,
     pub hollow: usize,
     pub snek: usize,
+    pub cultist: usize,
 }
 
 impl Default for EnemyIndices {
@@ -167,6 +187,7 @@ impl Default for EnemyIndices {
             rat: 23,
             hollow: 8,
             snek: 20,
+            cultist: 2,
         }
     }
 }
@@ -185,16 +206,6 @@ impl Default for TerrainIndices {
     }
 }
 
-#[derive(Default)]
-pub struct EguiWeapons {
-    pub pistol: EguiImage,
-    pub ray_gun: EguiImage,
-    pub shotgun: EguiImage,
-    pub boomerang: EguiImage,
-    pub smg: EguiImage,
-    pub grenade_launcher: EguiImage,
-}
-
 #[derive(Default)]
 pub struct EguiImage {
     pub id: TextureId,
@@ -208,70 +219,96 @@ pub struct EguiImages {
     pub empty_heart: EguiImage,
 
     pub dice: Vec<EguiImage>,
-    pub weapons: EguiWeapons,
-}
-
-#[derive(Deserialize)]
-pub struct SfxVolumes {
-    pub pistol: f32,
-    pub raygun: f32,
-    pub shotgun: f32,
-    pub smg: f32,
-    pub boomerang: f32,
-    pub grenade: f32,
-    pub grenade_explosion: f32,
-}
-
-impl Default for SfxVolumes {
-    fn default() -> Self {
-        Self {
-            pistol: 1.0,
-            raygun: 1.0,
-            shotgun: 1.0,
-            smg: 1.0,
-            boomerang: 1.0,
-            grenade: 1.0,
-            grenade_explosion: 1.0,
-        }
-    }
 }
 
 #[derive(Default, Deserialize, Asset, TypePath)]
 pub struct AudioConfig {
     pub bgm_loop_time: f64,
+    /// Volume for each sound effect, keyed the same as `AudioAssets::sfx`.
     #[serde(default)]
-    pub sfx_volumes: SfxVolumes,
+    pub sfx_volumes: HashMap<String, f32>,
 }
 
-#[derive(Resource, AssetCollection)]
-pub struct AudioAssets {
-    #[asset(key = "pistol")]
-    pub pistol: Handle<AudioSource>,
-    #[asset(key = "raygun")]
-    pub raygun: Handle<AudioSource>,
-    #[asset(key = "shotgun")]
-    pub shotgun: Handle<AudioSource>,
-    #[asset(key = "smg")]
-    pub smg: Handle<AudioSource>,
-    #[asset(key = "boomerang")]
-    pub boomerang: Handle<AudioSource>,
-    #[asset(key = "grenade")]
-    pub grenade: Handle<AudioSource>,
-    #[asset(key = "grenade_explosion")]
-    pub grenade_explosion: Handle<AudioSource>,
+impl AudioConfig {
+    pub fn sfx_volume(&self, key: &str) -> f64 {
+        self.sfx_volumes.get(key).copied().unwrap_or(1.0) as f64
+    }
+}
 
+#[derive(Default, Resource, AssetCollection)]
+pub struct AudioAssets {
     #[asset(key = "bgm")]
     pub bgm: Handle<AudioSource>,
 
     #[asset(path = "audio/config.audio.ron")]
     pub config: Handle<AudioConfig>,
+
+    /// Every sound in audio.assets.ron by its key, so data files can refer to sounds by key.
+    pub sfx: HashMap<String, Handle<AudioSource>>,
+}
+
+impl AudioAssets {
+    pub fn sfx(&self, key: &str) -> Option<Handle<AudioSource>> {
+        let handle = self.sfx.get(key).cloned();
+        if handle.is_none() {
+            warn!("No sound with key \"{}\"!", key);
+        }
+        handle
+    }
+}
+
+/// Plays sound effects at their volume from the audio config.
+#[derive(SystemParam)]
+pub struct Sfx<'w> {
+    sounds: Res<'w, AudioAssets>,
+    configs: Res<'w, Assets<AudioConfig>>,
+    audio: Res<'w, Audio>,
+}
+
+impl Sfx<'_> {
+    /// Starts playing the sound with this key, if there is one. The returned command can still
+    /// change how it plays.
+    pub fn play(&self, key: &str) -> Option<PlayAudioCommand<'_>> {
+        let config = self.configs.get(&self.sounds.config)
+            .expect("Audio config asset not loaded properly!");
+        let sound = self.sounds.sfx(key)?;
+        let mut command = self.audio.play(sound);
+        command.with_volume(config.sfx_volume(key));
+        Some(command)
+    }
+}
+
+/// The game assets along with the configs loaded from them, for systems that need several.
+#[derive(SystemParam)]
+pub struct Configs<'w> {
+    pub assets: Res<'w, GameAssets>,
+    weapons: Res<'w, Assets<WeaponsConfig>>,
+    upgrades: Res<'w, Assets<UpgradesConfig>>,
+    feel: Res<'w, Assets<GameFeelConfig>>,
+}
+
+impl Configs<'_> {
+    pub fn weapons(&self) -> Option<&WeaponsConfig> {
+        self.weapons.get(&self.assets.weapons)
+    }
+
+    pub fn upgrades(&self) -> Option<&UpgradesConfig> {
+        self.upgrades.get(&self.assets.upgrades)
+    }
+
+    pub fn feel(&self) -> Option<&GameFeelConfig> {
+        self.feel.get(&self.assets.feel)
+    }
 }
 
 fn assets_loaded(
     mut egui_ctx: EguiContexts,
     mut assets: ResMut<GameAssets>,
+    mut sounds: ResMut<AudioAssets>,
     mut animations: ResMut<Assets<Animation>>,
-    images: Res<Assets<Image>>,
+    mut images: ResMut<Assets<Image>>,
+    asset_server: Res<AssetServer>,
+    dynamic_assets: Res<DynamicAssets>,
 ) {
     debug!("Loaded assets!");
 
@@ -291,6 +328,14 @@ fn assets_loaded(
         assets.egui_images.empty_heart.id = egui_ctx.add_image(assets.empty_heart.clone_weak());
         assets.egui_images.empty_heart.size = image.size().as_vec2();
     }
+    let half_heart = images.get(&assets.whole_heart)
+        .zip(images.get(&assets.empty_heart))
+        .and_then(|(whole, empty)| make_half_heart(whole, empty));
+    if let Some(image) = half_heart {
+        assets.egui_images.half_heart.size = image.size().as_vec2();
+        assets.half_heart = images.add(image);
+        assets.egui_images.half_heart.id = egui_ctx.add_image(assets.half_heart.clone_weak());
+    }
 
     for handle in [assets.dice1.clone_weak(), assets.dice2.clone_weak(), assets.dice3.clone_weak(), assets.dice4.clone_weak(), assets.dice5.clone_weak(), assets.dice6.clone_weak()] {
         if let Some(image) = images.get(&handle) {
@@ -302,28 +347,31 @@ fn assets_loaded(
         }
     }
 
-    if let Some(image) = images.get(&assets.pistol) {
-        assets.egui_images.weapons.pistol.id = egui_ctx.add_image(assets.pistol.clone_weak());
-        assets.egui_images.weapons.pistol.size = image.size().as_vec2();
-    }
-    if let Some(image) = images.get(&assets.ray_gun) {
-        assets.egui_images.weapons.ray_gun.id = egui_ctx.add_image(assets.ray_gun.clone_weak());
-        assets.egui_images.weapons.ray_gun.size = image.size().as_vec2();
-    }
-    if let Some(image) = images.get(&assets.shotgun) {
-        assets.egui_images.weapons.shotgun.id = egui_ctx.add_image(assets.shotgun.clone_weak());
-        assets.egui_images.weapons.shotgun.size = image.size().as_vec2();
-    }
-    if let Some(image) = images.get(&assets.boomerang) {
-        assets.egui_images.weapons.boomerang.id = egui_ctx.add_image(assets.boomerang.clone_weak());
-        assets.egui_images.weapons.boomerang.size = image.size().as_vec2();
+    for (key, asset) in dynamic_assets.iter_assets() {
+        let handle = asset.load(&asset_server).into_iter()
+            .find_map(|handle| handle.try_typed::<AudioSource>().ok());
+        if let Some(handle) = handle {
+            sounds.sfx.insert(key.to_string(), handle);
+        }
     }
-    if let Some(image) = images.get(&assets.smg) {
-        assets.egui_images.weapons.smg.id = egui_ctx.add_image(assets.smg.clone_weak());
-        assets.egui_images.weapons.smg.size = image.size().as_vec2();
+}
+
+/// There's no half heart image, so use the left half of a whole heart and the right half of an
+/// empty one.
+fn make_half_heart(whole: &Image, empty: &Image) -> Option<Image> {
+    let format = whole.texture_descriptor.format;
+    if whole.size() != empty.size() || format != empty.texture_descriptor.format {
+        warn!("Whole and empty heart images don't match, can't make a half heart.");
+        return None;
     }
-    if let Some(image) = images.get(&assets.grenade_launcher) {
-        assets.egui_images.weapons.grenade_launcher.id = egui_ctx.add_image(assets.grenade_launcher.clone_weak());
-        assets.egui_images.weapons.grenade_launcher.size = image.size().as_vec2();
+
+    let pixel_bytes = format.block_copy_size(None)? as usize;
+    let row_bytes = whole.width() as usize * pixel_bytes;
+    let half_row_bytes = (whole.width() as usize / 2) * pixel_bytes;
+    let mut half = whole.clone();
+    for row in 0..whole.height() as usize {
+        let right_half = row * row_bytes + half_row_bytes..(row + 1) * row_bytes;
+        half.data[right_half.clone()].copy_from_slice(&empty.data[right_half]);
     }
+    Some(half)
 }
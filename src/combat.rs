@@ -1,13 +1,22 @@
+use std::collections::HashMap;
+
 use bevy::prelude::*;
+use bevy::ecs::{entity::Entities, system::SystemParam};
 use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
 
 use crate::{
     GAME_LOGIC_FRAME_TIME, AppState,
-    enemies::Enemy,
-    game::{Facing, GameTimers},
-    health::{EnemyHealth, PlayerHealth},
+    damage_numbers,
+    game::{Facing, GameLogicSet, GameTimers},
+    health::{self, EnemyHealth, PlayerHealth},
     physics::groups,
-    player::Player,
+    player::{Player, PostHitInvulnerability},
+    rng::GameRng,
+    settings::Settings,
+    stats::RunStats,
+    status::StatusEffectSpec,
+    weapons::FiredBy,
 };
 
 pub struct CombatPlugin;
@@ -17,19 +26,18 @@ impl Plugin for CombatPlugin {
         app
             .register_type::<Knockback>()
             .add_event::<HitEvent>()
-            .add_event::<PlayerHitEvent>()
-            .add_systems(Update, (
+            .add_systems(FixedUpdate, (
+                forget_old_hits.before(check_hits),
                 check_hits,
                 deal_hit_damage.after(check_hits),
                 deal_player_hit_damage.after(check_hits),
                 apply_hit_knockback.after(check_hits),
-                apply_player_hit_knockback.after(check_hits),
                 update_knockback,
-            ).run_if(in_state(AppState::InGame)));
+            ).in_set(GameLogicSet));
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Deserialize)]
 pub enum KnockbackDirection {
     AwayFromAttacker,
     // TowardAttacker,
@@ -48,17 +56,27 @@ impl KnockbackDirection {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize)]
 pub struct KnockbackSpec {
     pub direction: KnockbackDirection,
     pub frames: u8,
     pub distance: f32,
 }
 
+/// A hit box only hits things when it starts touching them, and keeps track of what it has hit so
+/// overlapping colliders or going back and forth over the same enemy don't count as extra hits.
 #[derive(Component)]
 pub struct HitSpec {
     damage: f32,
     knockback: Option<KnockbackSpec>,
+    statuses: Vec<StatusEffectSpec>,
+    /// From 0 to 1.
+    crit_chance: f32,
+    crit_multiplier: f32,
+    /// How long until it can hit the same thing again. If None, it only ever hits each thing once.
+    rehit_interval: Option<f32>,
+    /// When it last hit each thing, in game logic seconds.
+    hit_times: HashMap<Entity, f32>,
 }
 
 impl HitSpec {
@@ -70,6 +88,11 @@ impl HitSpec {
         Self {
             damage,
             knockback: None,
+            statuses: Vec::new(),
+            crit_chance: 0.0,
+            crit_multiplier: 1.0,
+            rehit_interval: None,
+            hit_times: HashMap::new(),
         }
     }
 
@@ -77,6 +100,65 @@ impl HitSpec {
         self.knockback = Some(knockback);
         self
     }
+
+    pub fn with_statuses(mut self, statuses: impl IntoIterator<Item = StatusEffectSpec>) -> Self {
+        self.statuses.extend(statuses);
+        self
+    }
+
+    pub fn with_crit(mut self, chance: f32, multiplier: f32) -> Self {
+        self.crit_chance = chance;
+        self.crit_multiplier = multiplier;
+        self
+    }
+
+    pub fn with_rehit_interval(mut self, seconds: f32) -> Self {
+        self.rehit_interval = Some(seconds.max(0.0));
+        self
+    }
+
+    pub fn damage(&self) -> f32 {
+        self.damage
+    }
+
+    /// Copies everything but the damage, which is scaled, and what it has already hit. For hit
+    /// boxes that break off from this one, like the pieces of a split projectile.
+    pub fn scaled(&self, damage_scale: f32) -> Self {
+        Self {
+            damage: self.damage * damage_scale.max(0.0),
+            knockback: self.knockback.clone(),
+            statuses: self.statuses.clone(),
+            crit_chance: self.crit_chance,
+            crit_multiplier: self.crit_multiplier,
+            rehit_interval: self.rehit_interval,
+            hit_times: HashMap::new(),
+        }
+    }
+
+    /// Records a hit on `defender` at `now` if it's allowed. Never allows two hits on the same
+    /// thing in the same step.
+    fn register_hit(&mut self, defender: Entity, now: f32) -> bool {
+        if let Some(&last) = self.hit_times.get(&defender) {
+            let Some(interval) = self.rehit_interval else {
+                return false;
+            };
+            if now <= last || now - last < interval {
+                return false;
+            }
+        }
+        self.hit_times.insert(defender, now);
+        true
+    }
+
+    /// Hits on things that are gone, or long enough ago that they can be hit again, don't need
+    /// remembering. Otherwise long-lived hit boxes, like enemy contact damage, remember every
+    /// hit for the whole run.
+    fn forget_old_hits(&mut self, now: f32, entities: &Entities) {
+        let rehit_interval = self.rehit_interval;
+        self.hit_times.retain(|&defender, &mut last| {
+            entities.contains(defender) && rehit_interval.map_or(true, |interval| now - last < interval)
+        });
+    }
 }
 
 #[derive(Bundle)]
@@ -96,8 +178,8 @@ impl HitBoxBundle {
         let filters = groups::HURT;
         Self {
             hit_box: HitSpec {
-                damage,
                 knockback,
+                ..HitSpec::new(damage)
             },
             transform: TransformBundle {
                 local: Transform::from_translation(offset.extend(0.0)),
@@ -144,131 +226,173 @@ pub struct HitEvent {
     pub defender: Entity,
     pub damage: f32,
     pub knockback: Option<KnockbackSpec>,
+    pub statuses: Vec<StatusEffectSpec>,
+    /// Crits are rolled when the damage is dealt.
+    pub crit_chance: f32,
+    pub crit_multiplier: f32,
 }
 
-#[derive(Event)]
-pub struct PlayerHitEvent {
-    pub enemy: Entity,
+/// Finds the rigid body a collider belongs to, which is what hits are dealt to.
+#[derive(SystemParam)]
+pub struct RigidBodies<'w, 's> {
+    rigid_body_q: Query<'w, 's, &'static RigidBody>,
+    parent_q: Query<'w, 's, &'static Parent>,
+    name_q: Query<'w, 's, &'static Name>,
+    groups_q: Query<'w, 's, &'static CollisionGroups>,
 }
 
-fn get_rigid_body_entity(
-    entity: Entity,
-    rigid_body_q: &Query<&RigidBody>,
-    parent_q: &Query<&Parent>,
-) -> Option<Entity> {
-    if rigid_body_q.contains(entity) {
-        return Some(entity);
-    }
+impl RigidBodies<'_, '_> {
+    /// The collider itself if it's a rigid body, otherwise its parent. Warns if neither is.
+    fn of_collider(&self, collider: Entity) -> Option<Entity> {
+        if self.rigid_body_q.contains(collider) {
+            return Some(collider);
+        }
 
-    if let Ok(parent) = parent_q.get(entity) {
-        if rigid_body_q.contains(parent.get()) {
-            return Some(parent.get());
+        if let Ok(parent) = self.parent_q.get(collider) {
+            if self.rigid_body_q.contains(parent.get()) {
+                return Some(parent.get());
+            }
         }
+
+        let name = self.name_q.get(collider).map(|name| name.as_str()).unwrap_or("None");
+        let groups = self.groups_q.get(collider).cloned().unwrap_or(CollisionGroups::default());
+        warn!("Collision happened with collider with no rigid body. Name: {}, Groups: {:?}", name, groups);
+        None
     }
+}
 
-    None
+fn forget_old_hits(
+    time: Res<Time>,
+    entities: &Entities,
+    mut hit_box_q: Query<&mut HitSpec>,
+) {
+    let now = time.elapsed_seconds();
+    for mut hit_box in hit_box_q.iter_mut() {
+        if !hit_box.hit_times.is_empty() {
+            hit_box.forget_old_hits(now, entities);
+        }
+    }
 }
 
 pub fn check_hits(
+    time: Res<Time>,
     mut collisions: EventReader<CollisionEvent>,
     mut hits: EventWriter<HitEvent>,
-    mut player_hits: EventWriter<PlayerHitEvent>,
-    parent_q: Query<&Parent>,
-    rigid_body_q: Query<&RigidBody>,
-    hit_box_q: Query<&HitSpec>,
+    rigid_bodies: RigidBodies,
+    mut hit_box_q: Query<&mut HitSpec>,
     hurt_box_q: Query<(), With<HurtBox>>,
-    player_q: Query<(Entity, &PlayerHealth), With<Player>>,
-    enemy_q: Query<(), With<Enemy>>,
-    name_q: Query<&Name>,
-    groups_q: Query<&CollisionGroups>,
 ) {
-    let (player_entity, health) = player_q.single();
-
+    let now = time.elapsed_seconds();
     // Listen for collision events involving a hit box and a hurt box and send a hit event.
     for collision in collisions.read() {
         if let &CollisionEvent::Started(e1, e2, _flags) = collision {
             // Get parent rigid body entities.
-            // TODO: Get rigid body entity. Check collider entity, if not found check parent entity.
-            let rbe1 = get_rigid_body_entity(e1, &rigid_body_q, &parent_q);
-            if rbe1.is_none() {
-                let name = name_q.get(e1).map(|name| name.as_str()).unwrap_or("None");
-                let groups = groups_q.get(e1).cloned().unwrap_or(CollisionGroups::default());
-                warn!("Collision happened with collider with no rigid body. Name: {}, Groups: {:?}", name, groups);
+            let Some(rbe1) = rigid_bodies.of_collider(e1) else {
                 continue;
-            }
-            let rbe1 = rbe1.unwrap();
-            let rbe2 = get_rigid_body_entity(e2, &rigid_body_q, &parent_q);
-            if rbe2.is_none() {
-                let name = name_q.get(e2).map(|name| name.as_str()).unwrap_or("None");
-                let groups = groups_q.get(e2).cloned().unwrap_or(CollisionGroups::default());
-                warn!("Collision happened with collider with no rigid body. Name: {}, Groups: {:?}", name, groups);
+            };
+            let Some(rbe2) = rigid_bodies.of_collider(e2) else {
+                continue;
+            };
+
+            let (hit_box_entity, attacker, defender) = if hit_box_q.contains(e1) && hurt_box_q.contains(e2) {
+                (e1, rbe1, rbe2)
+            } else if hit_box_q.contains(e2) && hurt_box_q.contains(e1) {
+                (e2, rbe2, rbe1)
+            } else {
+                continue;
+            };
+            let Ok(mut hit_box) = hit_box_q.get_mut(hit_box_entity) else {
+                continue;
+            };
+            // Registered by rigid body, so a defender with several hurt boxes is only hit once.
+            if !hit_box.register_hit(defender, now) {
+                trace!("Hit ignored, already hit that recently");
                 continue;
             }
-            let rbe2 = rbe2.unwrap();
-
-            // TODO: Dedup this code.
-            if let Ok(hit_box) = hit_box_q.get(e1) {
-                if hurt_box_q.contains(e2) {
-                    trace!("Hit event!");
-                    hits.send(HitEvent {
-                        attacker: rbe1,
-                        defender: rbe2,
-                        damage: hit_box.damage,
-                        knockback: hit_box.knockback.clone(),
-                    });
-                }
-            } else if let Ok(hit_box) = hit_box_q.get(e2) {
-                if hurt_box_q.contains(e1) {
-                    trace!("Hit event!");
-                    hits.send(HitEvent {
-                        attacker: rbe2,
-                        defender: rbe1,
-                        damage: hit_box.damage,
-                        knockback: hit_box.knockback.clone(),
-                    });
-                }
-            } else if player_entity == rbe1 && health.current > 0 && enemy_q.contains(rbe2) {
-                trace!("Player hit event!");
-                player_hits.send(PlayerHitEvent {
-                    enemy: rbe2,
-                });
-            } else if player_entity == rbe2 && health.current > 0 && enemy_q.contains(rbe1) {
-                trace!("Player hit event!");
-                player_hits.send(PlayerHitEvent {
-                    enemy: rbe1,
-                });
-            }
+
+            trace!("Hit event!");
+            hits.send(HitEvent {
+                attacker,
+                defender,
+                damage: hit_box.damage,
+                knockback: hit_box.knockback.clone(),
+                statuses: hit_box.statuses.clone(),
+                crit_chance: hit_box.crit_chance,
+                crit_multiplier: hit_box.crit_multiplier,
+            });
         }
     }
 }
 
 pub fn deal_hit_damage(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut rng: ResMut<GameRng>,
+    mut stats: ResMut<RunStats>,
     mut hits: EventReader<HitEvent>,
-    mut health_q: Query<&mut EnemyHealth>,
+    mut health_q: Query<(&mut EnemyHealth, &GlobalTransform)>,
+    fired_by_q: Query<&FiredBy>,
 ) {
     for hit in hits.read() {
-        if let Ok(mut health) = health_q.get_mut(hit.defender) {
-            health.lose_health(hit.damage);
+        let Ok((mut health, transform)) = health_q.get_mut(hit.defender) else {
+            continue;
+        };
+        // Hits that only apply status effects don't show a number.
+        if hit.damage <= 0.0 {
+            continue;
+        }
+
+        // Only roll for hits that can crit, so other hits don't change the crits that come up.
+        let crit = hit.crit_chance > 0.0 && rng.crits.f32() < hit.crit_chance;
+        let damage = if crit { hit.damage * hit.crit_multiplier } else { hit.damage };
+        health.lose_health(damage);
+        if let Ok(fired_by) = fired_by_q.get(hit.attacker) {
+            stats.record_damage(fired_by, damage);
+        }
+
+        if settings.damage_numbers {
+            damage_numbers::spawn_damage_number(damage, crit, transform.translation().truncate(), &mut commands);
         }
     }
 }
 
+/// The player only takes damage from one hit per frame, whichever hits hardest. Hits on the player
+/// deal damage in hearts, so this returns half hearts.
+pub fn player_hit_damage<'a>(
+    hits: impl Iterator<Item = &'a HitEvent>,
+    player: Entity,
+) -> u8 {
+    hits
+        .filter(|hit| hit.defender == player)
+        .map(|hit| health::half_hearts(hit.damage))
+        .max()
+        .unwrap_or(0)
+}
+
 pub fn deal_player_hit_damage(
+    mut next_state: ResMut<NextState<AppState>>,
     mut game_timers: ResMut<GameTimers>,
-    mut hits: EventReader<PlayerHitEvent>,
-    mut health_q: Query<&mut PlayerHealth>,
+    mut hits: EventReader<HitEvent>,
+    mut player_q: Query<(Entity, &mut PlayerHealth, &mut PostHitInvulnerability), With<Player>>,
 ) {
-    // TODO: Only allow taking damage from one source in a frame.
-    let took_damage = hits.read().count() > 0;
-    if took_damage {
-        if let Ok(mut health) = health_q.get_single_mut() {
-            health.lose_health(1);
-
-            if health.current == 0 {
-                // Player just died!
-                game_timers.game_time.pause();
-                game_timers.reset_time.unpause();
-            }
+    let Ok((player, mut health, mut invuln)) = player_q.get_single_mut() else {
+        return;
+    };
+    if health.current == 0 {
+        return;
+    }
+
+    let damage = player_hit_damage(hits.read(), player);
+    if damage > 0 {
+        health.lose_health(damage);
+
+        if health.current == 0 {
+            // Player just died!
+            game_timers.game_time.pause();
+            game_timers.reset_time.unpause();
+            next_state.set(AppState::GameOver);
+        } else {
+            invuln.start();
         }
     }
 }
@@ -292,23 +416,6 @@ fn apply_hit_knockback(
     }
 }
 
-fn apply_player_hit_knockback(
-    mut hits: EventReader<PlayerHitEvent>,
-    mut knockback_q: Query<(Entity, &mut Knockback), With<Player>>,
-    transform_q: Query<(&GlobalTransform, &Facing)>,
-) {
-    for hit in hits.read() {
-        if let Ok((player_entity, mut knockback)) = knockback_q.get_single_mut() {
-            if let Ok([(atk_transform, atk_facing), (def_transform, _)]) = transform_q.get_many([hit.enemy, player_entity]) {
-                let (atk_pos, def_pos) = (atk_transform.translation().truncate(), def_transform.translation().truncate());
-                let direction = KnockbackDirection::AwayFromAttacker.compute_direction(atk_pos, def_pos, atk_facing.dir);
-                let offset = direction * 25.0;
-                knockback.start(10, offset);
-            }
-        }
-    }
-}
-
 #[derive(Default, Component, Reflect)]
 #[reflect(Component)]
 pub struct Knockback {
@@ -327,7 +434,7 @@ impl Knockback {
     }
 }
 
-fn update_knockback(
+pub fn update_knockback(
     time: Res<Time>,
     mut knockback_q: Query<(&mut Knockback, Option<&mut Velocity>)>,
 ) {
@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+
+use crate::game::{GameLogicSet, Lifetime, RunEntity};
+
+const DAMAGE_NUMBER_LIFETIME: f32 = 0.6;
+const DAMAGE_NUMBER_RISE_SPEED: f32 = 30.0;
+const DAMAGE_NUMBER_Z: f32 = 20.0;
+/// Starts a bit above where the hit landed, so it isn't covered by the enemy.
+const DAMAGE_NUMBER_OFFSET: f32 = 8.0;
+const DAMAGE_NUMBER_SIZE: f32 = 10.0;
+const CRIT_NUMBER_SIZE: f32 = 14.0;
+const CRIT_COLOR: Color = Color::srgb(1.0, 0.8, 0.2);
+
+/// Numbers that pop up over enemies showing how much damage each hit dealt.
+pub struct DamageNumbersPlugin;
+
+impl Plugin for DamageNumbersPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(FixedUpdate, float_damage_numbers
+                .before(crate::game::update_lifetimes)
+                .in_set(GameLogicSet));
+    }
+}
+
+#[derive(Component)]
+pub struct DamageNumber {
+    pub amount: f32,
+    pub crit: bool,
+}
+
+pub fn spawn_damage_number(amount: f32, crit: bool, pos: Vec2, commands: &mut Commands) -> Entity {
+    // Whole numbers without the decimal, anything else to one decimal place.
+    let rounded = (amount * 10.0).round() / 10.0;
+    let (text, font_size, color) = if crit {
+        (format!("{}!", rounded), CRIT_NUMBER_SIZE, CRIT_COLOR)
+    } else {
+        (format!("{}", rounded), DAMAGE_NUMBER_SIZE, Color::WHITE)
+    };
+
+    let pos = pos + Vec2::Y * DAMAGE_NUMBER_OFFSET;
+    commands.spawn((
+        Name::new("DamageNumber"),
+        DamageNumber {
+            amount,
+            crit,
+        },
+        Text2dBundle {
+            text: Text::from_section(text, TextStyle {
+                font_size,
+                color,
+                ..default()
+            }),
+            transform: Transform::from_translation(pos.extend(DAMAGE_NUMBER_Z)),
+            ..default()
+        },
+        Lifetime::new(DAMAGE_NUMBER_LIFETIME),
+        RunEntity,
+    )).id()
+}
+
+/// Rises and fades out over its lifetime.
+fn float_damage_numbers(
+    time: Res<Time>,
+    mut number_q: Query<(&mut Transform, &mut Text, &Lifetime), With<DamageNumber>>,
+) {
+    let dt = time.delta_seconds();
+    for (mut transform, mut text, lifetime) in number_q.iter_mut() {
+        transform.translation.y += DAMAGE_NUMBER_RISE_SPEED * dt;
+
+        let alpha = (lifetime.remaining / lifetime.lifetime).clamp(0.0, 1.0);
+        for section in text.sections.iter_mut() {
+            section.style.color.set_alpha(alpha);
+        }
+    }
+}
@@ -6,11 +6,13 @@ use bevy_rapier2d::render::{DebugRenderContext, RapierDebugRenderPlugin};
 
 use crate::{
     AppState,
-    assets::AudioAssets,
+    assets::{AudioAssets, GameAssets},
     enemies::spawner::Spawner,
     game::{Bgm, GameTimers},
     player::{self, PlayerInput},
-    weapons::{Weapon, WeaponChoice},
+    rng::GameRng,
+    upgrades::Upgrades,
+    weapons::{Weapon, WeaponChoice, WeaponsConfig},
     window::primary_window_exists,
 };
 
@@ -63,6 +65,8 @@ fn debug_ui(
     mut debug_state: ResMut<DebugState>,
     mut debug_physics_ctx: ResMut<DebugRenderContext>,
     mut egui_ctx: EguiContexts,
+    rng: Res<GameRng>,
+    mut spawner_q: Query<&mut Spawner>,
 ) {
     let ctx = egui_ctx.ctx_mut();
 
@@ -76,6 +80,21 @@ fn debug_ui(
                     ui.checkbox(&mut debug_state.show_world_inspector, "World Inspector");
                     ui.checkbox(&mut debug_physics_ctx.enabled, "Debug Physics Render");
                 });
+                if let Ok(mut spawner) = spawner_q.get_single_mut() {
+                    ui.menu_button("Spawner", |ui| {
+                        // Setting the difficulty by hand stops the wave schedule from changing it.
+                        ui.checkbox(&mut spawner.follow_schedule, "Follow Wave Schedule");
+                        let mut max_enemies = spawner.max_enemies;
+                        let mut spawn_rate = spawner.spawn_rate;
+                        let max_changed = ui.add(egui::DragValue::new(&mut max_enemies).prefix("Max Enemies: ")).changed();
+                        let rate_changed = ui.add(egui::DragValue::new(&mut spawn_rate).prefix("Spawn Rate: ").speed(0.01).range(0.05..=10.0)).changed();
+                        if max_changed || rate_changed {
+                            spawner.follow_schedule = false;
+                            spawner.set_difficulty(max_enemies, spawn_rate);
+                        }
+                    });
+                }
+                ui.label(format!("Seed: {}", rng.seed()));
             });
         });
 }
@@ -83,11 +102,14 @@ fn debug_ui(
 
 fn update_mouse_cursor(
     debug_state: Res<DebugState>,
+    app_state: Res<State<AppState>>,
     mut window_q: Query<&mut Window, With<PrimaryWindow>>,
 ) {
     if let Ok(mut window) = window_q.get_single_mut() {
         // TODO: Make UI egui windows non-interactable and remove the debug_state.enabled check.
-        let show_cursor = debug_state.enabled; //&& egui_ctx.ctx_mut().wants_pointer_input();
+        // Menus need the cursor to click buttons.
+        let in_game = *app_state.get() == AppState::InGame;
+        let show_cursor = debug_state.enabled || !in_game; //&& egui_ctx.ctx_mut().wants_pointer_input();
         window.cursor.visible = show_cursor;
     }
 }
@@ -108,26 +130,36 @@ fn toggle_debug_ui(
 
 fn select_weapon(
     keys: ResMut<ButtonInput<KeyCode>>,
+    assets: Res<GameAssets>,
+    weapons_configs: Res<Assets<WeaponsConfig>>,
     mut egui_ctx: EguiContexts,
-    mut player_q: Query<&mut Weapon, With<PlayerInput>>,
+    mut player_q: Query<(&mut Weapon, &Upgrades), With<PlayerInput>>,
 ) {
     if egui_ctx.ctx_mut().wants_keyboard_input() {
         return;
     }
 
-    let mut weapon = player_q.single_mut();
+    let Some(weapons) = weapons_configs.get(&assets.weapons) else {
+        return;
+    };
+    let (mut weapon, upgrades) = player_q.single_mut();
 
     for key in keys.get_just_pressed() {
+        // Number keys select weapons in the order they're listed in the weapons config.
         let choice = match key {
-            KeyCode::Digit1 => WeaponChoice::Pistol,
-            KeyCode::Digit2 => WeaponChoice::RayGun,
-            KeyCode::Digit3 => WeaponChoice::Shotgun,
-            KeyCode::Digit4 => WeaponChoice::Boomerang,
-            KeyCode::Digit5 => WeaponChoice::Smg,
-            KeyCode::Digit6 => WeaponChoice::GrenadeLauncher,
+            KeyCode::Digit1 => WeaponChoice(0),
+            KeyCode::Digit2 => WeaponChoice(1),
+            KeyCode::Digit3 => WeaponChoice(2),
+            KeyCode::Digit4 => WeaponChoice(3),
+            KeyCode::Digit5 => WeaponChoice(4),
+            KeyCode::Digit6 => WeaponChoice(5),
+            KeyCode::Digit7 => WeaponChoice(6),
+            KeyCode::Digit8 => WeaponChoice(7),
             _ => continue,
         };
-        *weapon = Weapon::new(choice);
+        if weapons.contains(choice) {
+            *weapon = Weapon::new(choice, weapons, upgrades);
+        }
     }
 }
 
@@ -1,17 +1,24 @@
+use std::time::Duration;
+
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
 
 use crate::{
-    AppState,
     animation::{Animation, AnimationState, Play},
-    assets::GameAssets,
-    combat::{self, HurtBoxBundle, Knockback},
-    game::{Facing, Lifetime},
+    assets::{EnemyIndices, GameAssets},
+    combat::{self, HitSpec, HurtBoxBundle, Knockback, KnockbackDirection, KnockbackSpec},
+    game::{Facing, GameLogicSet, Lifetime, RunEntity},
     health::EnemyHealth,
+    interpolation::Interpolated,
     physics::{groups, ColliderBundle},
     player::Player,
+    status::StatusEffects,
+    weapons::{DieOnHit, ProjectileBundle},
 };
 
+pub mod boss;
+pub mod formation;
 pub mod spawner;
 
 pub struct EnemiesPlugin;
@@ -19,76 +26,278 @@ pub struct EnemiesPlugin;
 impl Plugin for EnemiesPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_plugins(spawner::SpawnerPlugin)
-            .add_systems(Update, follow_player_ai.run_if(in_state(AppState::InGame)))
-            .add_systems(Update, trigger_enemy_death.run_if(in_state(AppState::InGame)).after(combat::deal_hit_damage))
-            .add_systems(Update, despawn_dead_enemies.run_if(in_state(AppState::InGame)).after(trigger_enemy_death));
+            .add_plugins((
+                boss::BossPlugin,
+                spawner::SpawnerPlugin,
+            ))
+            .add_systems(FixedUpdate, (
+                follow_player_ai,
+                zig_zag_ai.after(follow_player_ai),
+                phasing_ai,
+                ranged_ai,
+                trigger_enemy_death.after(combat::deal_hit_damage),
+                despawn_dead_enemies.after(trigger_enemy_death),
+            ).in_set(GameLogicSet));
     }
 }
 
 #[derive(Component)]
 pub struct Enemy;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Component, Deserialize)]
+pub enum EnemyKind {
+    Rat,
+    /// Fast and fragile, zig-zags toward the player.
+    Snek,
+    /// Slow and tanky, phases in and out of collision.
+    Hollow,
+    /// Keeps its distance and shoots at the player.
+    Cultist,
+}
+
+impl EnemyKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Rat => "Rat",
+            Self::Snek => "Snek",
+            Self::Hollow => "Hollow",
+            Self::Cultist => "Cultist",
+        }
+    }
+
+    pub fn stats(&self) -> EnemyStats {
+        match self {
+            Self::Rat => EnemyStats {
+                health: 10.0,
+                speed: 50.0,
+                size: Vec2::new(13.0, 11.0),
+                contact_damage: 1.0,
+            },
+            Self::Snek => EnemyStats {
+                health: 4.0,
+                speed: 80.0,
+                size: Vec2::new(12.0, 8.0),
+                contact_damage: 0.5,
+            },
+            Self::Hollow => EnemyStats {
+                health: 30.0,
+                speed: 25.0,
+                size: Vec2::new(14.0, 14.0),
+                contact_damage: 1.5,
+            },
+            Self::Cultist => EnemyStats {
+                health: 8.0,
+                speed: 40.0,
+                size: Vec2::new(12.0, 13.0),
+                contact_damage: 0.5,
+            },
+        }
+    }
+
+    fn sprite_index(&self, indices: &EnemyIndices) -> usize {
+        match self {
+            Self::Rat => indices.rat,
+            Self::Snek => indices.snek,
+            Self::Hollow => indices.hollow,
+            Self::Cultist => indices.cultist,
+        }
+    }
+}
+
+pub struct EnemyStats {
+    pub health: f32,
+    pub speed: f32,
+    /// Size of the body and hurt box. The hit box is a bit smaller, so touching isn't quite enough
+    /// to get hurt.
+    pub size: Vec2,
+    /// How many hearts the player loses when touching this enemy.
+    pub contact_damage: f32,
+}
+
 #[derive(Component)]
 #[component(storage = "SparseSet")]
 pub struct Death;
 
 #[derive(Component)]
-pub struct AiFollowPlayer;
+pub struct AiFollowPlayer {
+    pub speed: f32,
+}
+
+/// Weaves side to side while following the player.
+#[derive(Default, Component)]
+pub struct AiZigZag {
+    time: f32,
+}
+
+const ZIG_ZAG_FREQUENCY: f32 = 1.5;
+const ZIG_ZAG_SPEED: f32 = 70.0;
+
+/// Alternates between being solid and phased out. While phased out, the enemy passes through
+/// everything and can't hurt or be hurt.
+#[derive(Component)]
+pub struct AiPhasing {
+    timer: Timer,
+    phased: bool,
+    /// Colliders to turn off while phased out.
+    colliders: Vec<Entity>,
+    /// Groups to restore once solid again.
+    solid_groups: Vec<CollisionGroups>,
+}
 
-pub fn spawn_basic_enemy(
+const PHASING_SOLID_SECS: f32 = 3.0;
+const PHASING_PHASED_SECS: f32 = 1.5;
+
+impl AiPhasing {
+    fn new(colliders: Vec<Entity>) -> Self {
+        Self {
+            timer: Timer::from_seconds(PHASING_SOLID_SECS, TimerMode::Repeating),
+            phased: false,
+            colliders,
+            solid_groups: Vec::new(),
+        }
+    }
+
+    pub fn phased(&self) -> bool {
+        self.phased
+    }
+}
+
+/// Stays between these distances from the player while not shooting.
+const RANGED_MIN_DISTANCE: f32 = 100.0;
+const RANGED_MAX_DISTANCE: f32 = 140.0;
+/// Only shoots when the player is closer than this, so it doesn't fire from off screen.
+const RANGED_FIRE_DISTANCE: f32 = 180.0;
+const RANGED_COOLDOWN_SECS: f32 = 2.5;
+/// How long it stands still and glows before shooting, so the player can see it coming.
+const RANGED_WINDUP_SECS: f32 = 0.6;
+const RANGED_WINDUP_COLOR: Color = Color::srgb(1.0, 0.3, 0.3);
+const RANGED_PROJECTILE_SPEED: f32 = 90.0;
+const RANGED_PROJECTILE_DAMAGE: f32 = 1.0;
+
+const HOSTILE_PROJECTILE_LIFETIME: f32 = 4.0;
+const HOSTILE_PROJECTILE_COLOR: Color = Color::srgb(1.0, 0.4, 0.4);
+
+pub(crate) const CONTACT_KNOCKBACK: KnockbackSpec = KnockbackSpec {
+    direction: KnockbackDirection::AwayFromAttacker,
+    frames: 10,
+    distance: 25.0,
+};
+
+/// Keeps its distance from the player and shoots at them, standing still for a moment before
+/// each shot.
+#[derive(Component)]
+pub struct AiRanged {
+    speed: f32,
+    cooldown: Timer,
+    windup: Timer,
+    winding_up: bool,
+}
+
+impl AiRanged {
+    fn new(speed: f32) -> Self {
+        Self {
+            speed,
+            cooldown: Timer::from_seconds(RANGED_COOLDOWN_SECS, TimerMode::Once),
+            windup: Timer::from_seconds(RANGED_WINDUP_SECS, TimerMode::Once),
+            winding_up: false,
+        }
+    }
+
+    pub fn winding_up(&self) -> bool {
+        self.winding_up
+    }
+
+    /// The color it glows while winding up a shot.
+    pub fn telegraph_color(&self) -> Option<Color> {
+        self.winding_up.then_some(RANGED_WINDUP_COLOR)
+    }
+}
+
+pub fn spawn_enemy(
+    kind: EnemyKind,
     pos: Vec2,
     commands: &mut Commands,
     assets: &GameAssets,
 ) -> Entity {
+    let stats = kind.stats();
+
     let groups = groups::ENEMY;
     let masks = groups::WORLD | groups::ENEMY;
-    let collider = ColliderBundle::new(Vec2::new(13.0, 11.0), Vec2::ZERO, groups, masks);
+    let collider = ColliderBundle::new(stats.size, Vec2::ZERO, groups, masks);
     let collider = commands.spawn(collider)
         .insert(Name::new("EnemyCollider"))
         .id();
 
     let groups = groups::HIT;
     let masks = groups::PLAYER;
-    let hit_box = ColliderBundle::new(Vec2::new(11.0, 9.0), Vec2::ZERO, groups, masks);
+    let hit_box = ColliderBundle::new(stats.size - 2.0, Vec2::ZERO, groups, masks);
+    let contact_hit = HitSpec::new(stats.contact_damage)
+        .with_knockback(CONTACT_KNOCKBACK)
+        // Bumping into the player again always hurts.
+        .with_rehit_interval(0.0);
     let hit_box = commands.spawn(hit_box)
+        .insert(contact_hit)
         .insert(Name::new("EnemyHitBox"))
         .id();
 
-    let hurt_box = HurtBoxBundle::new(Vec2::new(13.0, 11.0), Vec2::ZERO, groups::ENEMY);
+    let hurt_box = HurtBoxBundle::new(stats.size, Vec2::ZERO, groups::ENEMY);
     let hurt_box = commands.spawn(hurt_box)
         .insert(Name::new("EnemyHurtBox"))
         .id();
 
-    let enemy_bundle = BasicEnemyBundle::new(pos, assets.enemy.clone(), assets.enemy_atlas.clone(), assets.enemy_indices.rat);
-    commands.spawn(enemy_bundle)
+    let sprite_index = kind.sprite_index(&assets.enemy_indices);
+    let enemy_bundle = EnemyBundle::new(kind, pos, assets.enemy.clone(), assets.enemy_atlas.clone(), sprite_index);
+    let mut enemy = commands.spawn(enemy_bundle);
+    enemy
         .add_child(collider)
         .add_child(hit_box)
-        .add_child(hurt_box)
-        .id()
+        .add_child(hurt_box);
+
+    let follow = AiFollowPlayer { speed: stats.speed };
+    match kind {
+        EnemyKind::Rat => {
+            enemy.insert(follow);
+        }
+        EnemyKind::Snek => {
+            enemy.insert((follow, AiZigZag::default()));
+        }
+        EnemyKind::Hollow => {
+            enemy.insert((follow, AiPhasing::new(vec![collider, hit_box, hurt_box])));
+        }
+        EnemyKind::Cultist => {
+            enemy.insert(AiRanged::new(stats.speed));
+        }
+    }
+
+    enemy.id()
 }
 
 #[derive(Bundle)]
-pub struct BasicEnemyBundle {
+pub struct EnemyBundle {
     enemy: Enemy,
+    kind: EnemyKind,
     name: Name,
     sprite: SpriteBundle,
     atlas: TextureAtlas,
     facing: Facing,
     health: EnemyHealth,
     knockback: Knockback,
-    ai: AiFollowPlayer,
+    status: StatusEffects,
+    interpolated: Interpolated,
+    run_entity: RunEntity,
 
     rigid_body: RigidBody,
     rotation_constraints: LockedAxes,
     velocity: Velocity,
 }
 
-impl BasicEnemyBundle {
-    pub fn new(pos: Vec2, texture: Handle<Image>, atlas: Handle<TextureAtlasLayout>, sprite_index: usize) -> Self {
+impl EnemyBundle {
+    pub fn new(kind: EnemyKind, pos: Vec2, texture: Handle<Image>, atlas: Handle<TextureAtlasLayout>, sprite_index: usize) -> Self {
+        let stats = kind.stats();
         Self {
             enemy: Enemy,
-            name: Name::new("BasicEnemy"),
+            kind,
+            name: Name::new(kind.name()),
             sprite: SpriteBundle {
                 texture,
                 transform: Transform::from_translation(pos.extend(8.0)),
@@ -99,9 +308,11 @@ impl BasicEnemyBundle {
                 index: sprite_index,
             },
             facing: Facing { dir: Vec2::X },
-            health: EnemyHealth::new(10.0),
+            health: EnemyHealth::new(stats.health),
             knockback: default(),
-            ai: AiFollowPlayer,
+            status: default(),
+            interpolated: default(),
+            run_entity: RunEntity,
             rigid_body: RigidBody::Dynamic,
             rotation_constraints: LockedAxes::ROTATION_LOCKED,
             velocity: default(),
@@ -111,26 +322,174 @@ impl BasicEnemyBundle {
 
 fn follow_player_ai(
     player_q: Query<&Transform, With<Player>>,
-    mut ai_q: Query<(&mut Velocity, &mut Facing, &Transform, &Knockback), With<AiFollowPlayer>>,
+    mut ai_q: Query<(&mut Velocity, &mut Facing, &Transform, &Knockback, &StatusEffects, &AiFollowPlayer)>,
 ) {
     if let Ok(player_transform) = player_q.get_single() {
-        for (mut velocity, mut facing, transform, knockback) in ai_q.iter_mut() {
+        for (mut velocity, mut facing, transform, knockback, status, ai) in ai_q.iter_mut() {
             if knockback.is_active() {
-                return;
+                continue;
+            }
+            if status.is_stunned() {
+                velocity.linvel = Vec2::ZERO;
+                continue;
             }
 
             let dir = player_transform.translation.truncate() - transform.translation.truncate();
             let dir = dir.normalize_or_zero();
-            let speed = 50.0;
-            velocity.linvel = dir * speed;
+            velocity.linvel = dir * ai.speed * status.speed_multiplier();
             facing.dir = dir;
         }
     }
 }
 
-fn trigger_enemy_death(
+fn zig_zag_ai(
+    time: Res<Time>,
+    mut ai_q: Query<(&mut Velocity, &Facing, &Knockback, &StatusEffects, &mut AiZigZag)>,
+) {
+    for (mut velocity, facing, knockback, status, mut ai) in ai_q.iter_mut() {
+        ai.time += time.delta_seconds();
+        if knockback.is_active() {
+            continue;
+        }
+
+        // Weave across the direction toward the player.
+        let weave = (ai.time * ZIG_ZAG_FREQUENCY * std::f32::consts::TAU).sin();
+        velocity.linvel += facing.dir.perp() * weave * ZIG_ZAG_SPEED * status.speed_multiplier();
+    }
+}
+
+fn phasing_ai(
+    time: Res<Time>,
+    mut ai_q: Query<(&mut AiPhasing, &mut Sprite)>,
+    mut groups_q: Query<&mut CollisionGroups>,
+) {
+    for (mut ai, mut sprite) in ai_q.iter_mut() {
+        ai.timer.tick(time.delta());
+        if !ai.timer.just_finished() {
+            continue;
+        }
+
+        ai.phased = !ai.phased;
+        let (secs, alpha) = if ai.phased {
+            (PHASING_PHASED_SECS, 0.4)
+        } else {
+            (PHASING_SOLID_SECS, 1.0)
+        };
+        ai.timer.set_duration(Duration::from_secs_f32(secs));
+        sprite.color.set_alpha(alpha);
+
+        if ai.phased {
+            let ai = &mut *ai;
+            ai.solid_groups.clear();
+            for &entity in ai.colliders.iter() {
+                if let Ok(mut groups) = groups_q.get_mut(entity) {
+                    ai.solid_groups.push(*groups);
+                    *groups = CollisionGroups::new(Group::NONE, Group::NONE);
+                }
+            }
+        } else {
+            for (&entity, &solid_groups) in ai.colliders.iter().zip(ai.solid_groups.iter()) {
+                if let Ok(mut groups) = groups_q.get_mut(entity) {
+                    *groups = solid_groups;
+                }
+            }
+        }
+    }
+}
+
+/// Spawns a projectile that only hits the player.
+fn spawn_hostile_projectile(
+    pos: Vec2,
+    velocity: Vec2,
+    damage: f32,
+    commands: &mut Commands,
+    assets: &GameAssets,
+) -> Entity {
+    let speed = velocity.length();
+    let dir = velocity.normalize_or_zero();
+    let projectile = ProjectileBundle::new(speed, pos, dir, assets.projectiles.clone(), assets.projectile_atlas.clone(), assets.projectile_indices.orb)
+        .with_color(HOSTILE_PROJECTILE_COLOR);
+    commands.spawn((
+        projectile,
+        Name::new("EnemyProjectile"),
+        HitSpec::new(damage),
+        Collider::ball(3.0),
+        // Only hits the player's hurt box.
+        CollisionGroups::new(groups::HIT, groups::PLAYER),
+        Sensor,
+        ActiveEvents::COLLISION_EVENTS,
+        DieOnHit,
+        Lifetime::new(HOSTILE_PROJECTILE_LIFETIME),
+    )).id()
+}
+
+/// The glow while winding up is drawn by `status::color_sprites`, along with status effect tints.
+pub fn ranged_ai(
     mut commands: Commands,
-    q: Query<(Entity, &EnemyHealth), (With<Enemy>, Changed<EnemyHealth>)>,
+    time: Res<Time>,
+    assets: Res<GameAssets>,
+    player_q: Query<&Transform, With<Player>>,
+    mut ai_q: Query<(&mut AiRanged, &mut Velocity, &mut Facing, &Transform, &Knockback, &StatusEffects)>,
+) {
+    let Ok(player_transform) = player_q.get_single() else {
+        return;
+    };
+
+    for (mut ai, mut velocity, mut facing, transform, knockback, status) in ai_q.iter_mut() {
+        if knockback.is_active() {
+            continue;
+        }
+        // Stunned cultists don't move or wind up their shots.
+        if status.is_stunned() {
+            velocity.linvel = Vec2::ZERO;
+            continue;
+        }
+
+        let pos = transform.translation.truncate();
+        let to_player = player_transform.translation.truncate() - pos;
+        let distance = to_player.length();
+        let dir = to_player.normalize_or_zero();
+        facing.dir = dir;
+
+        if ai.winding_up {
+            velocity.linvel = Vec2::ZERO;
+            ai.windup.tick(time.delta());
+            if !ai.windup.finished() {
+                continue;
+            }
+
+            ai.winding_up = false;
+            ai.cooldown.reset();
+
+            spawn_hostile_projectile(pos, dir * RANGED_PROJECTILE_SPEED, RANGED_PROJECTILE_DAMAGE, &mut commands, &assets);
+            continue;
+        }
+
+        ai.cooldown.tick(time.delta());
+        if ai.cooldown.finished() && distance < RANGED_FIRE_DISTANCE {
+            ai.winding_up = true;
+            ai.windup.reset();
+            velocity.linvel = Vec2::ZERO;
+            continue;
+        }
+
+        let speed = ai.speed * status.speed_multiplier();
+        velocity.linvel = if distance > RANGED_MAX_DISTANCE {
+            dir * speed
+        } else if distance < RANGED_MIN_DISTANCE {
+            -dir * speed
+        } else {
+            Vec2::ZERO
+        };
+    }
+}
+
+/// Enemies whose health changed this tick.
+type DamagedEnemy = (With<Enemy>, Changed<EnemyHealth>);
+
+pub fn trigger_enemy_death(
+    mut commands: Commands,
+    q: Query<(Entity, &EnemyHealth), DamagedEnemy>,
 ) {
     // TODO: Death events??
     for (entity, health) in q.iter() {
@@ -141,10 +500,13 @@ fn trigger_enemy_death(
     }
 }
 
-fn despawn_dead_enemies(
+/// Enemies that died this tick.
+type NewlyDeadEnemy = (With<Enemy>, Added<Death>);
+
+pub fn despawn_dead_enemies(
     mut commands: Commands,
     assets: Res<GameAssets>,
-    q: Query<(Entity, &GlobalTransform), (With<Enemy>, Added<Death>)>,
+    q: Query<(Entity, &GlobalTransform), NewlyDeadEnemy>,
 ) {
     for (entity, transform) in q.iter() {
         commands.entity(entity).despawn_recursive();
@@ -172,6 +534,7 @@ struct VfxBundle {
     anim_state: AnimationState,
     play: Play,
     lifetime: Lifetime,
+    run_entity: RunEntity,
 }
 
 impl VfxBundle {
@@ -191,6 +554,7 @@ impl VfxBundle {
             anim_state: AnimationState::default(),
             play: Play,
             lifetime: Lifetime::new(0.4),
+            run_entity: RunEntity,
         }
     }
 }
@@ -0,0 +1,495 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::math::Mat2;
+use bevy::reflect::TypePath;
+use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::plugin::PhysicsSet;
+use serde::Deserialize;
+
+use crate::{
+    InRun,
+    assets::GameAssets,
+    combat::{self, HitSpec, HurtBoxBundle},
+    enemies::{self, Death, Enemy, EnemyKind, CONTACT_KNOCKBACK},
+    game::{Facing, GameLogicSet, GameTimers, RunEntity},
+    health::EnemyHealth,
+    interpolation::Interpolated,
+    physics::{groups, ColliderBundle},
+    pickups::{self, PickupKind},
+    player::{self, Player},
+    rng::GameRng,
+};
+
+/// How far from the player a boss shows up, inside its arena.
+const BOSS_SPAWN_DISTANCE: f32 = 120.0;
+const BOSS_Z: f32 = 9.0;
+const BOSS_WINDUP_COLOR: Color = Color::srgb(1.0, 0.3, 0.3);
+/// Number of markers drawn around the edge of an arena.
+const ARENA_MARKERS: usize = 48;
+const ARENA_MARKER_COLOR: Color = Color::srgba(1.0, 0.4, 0.4, 0.8);
+
+pub struct BossPlugin;
+
+impl Plugin for BossPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(FixedUpdate, (
+                // One boss at a time.
+                spawn_bosses.run_if(no_boss_alive),
+                update_boss_phase,
+                boss_ai.after(update_boss_phase),
+                // Anything moving the player afterwards could push them back out.
+                lock_player_in_arena
+                    .after(combat::update_knockback)
+                    .after(player::update_player_movement),
+                drop_boss_rewards
+                    .after(enemies::trigger_enemy_death)
+                    .before(enemies::despawn_dead_enemies),
+            ).in_set(GameLogicSet))
+            // Bodies bumping into the player during the physics step can still push them out.
+            .add_systems(FixedUpdate, lock_player_in_arena
+                .after(PhysicsSet::Writeback)
+                .run_if(in_state(InRun)));
+    }
+}
+
+#[derive(Deserialize, Asset, TypePath)]
+pub struct BossesConfig {
+    /// Sorted by spawn time.
+    pub bosses: Vec<BossDef>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct BossDef {
+    pub name: String,
+    /// Survival time in seconds when it shows up. If the last boss is still alive by then, it
+    /// shows up once that one dies.
+    pub spawn_time: f32,
+    /// Which enemy it looks like, drawn at `size`.
+    pub sprite: EnemyKind,
+    pub size: f32,
+    pub collider_size: Vec2,
+    pub health: f32,
+    /// In hearts.
+    pub contact_damage: f32,
+    /// The player can't leave this radius around where they were when the boss showed up until
+    /// it dies.
+    pub arena_radius: f32,
+    /// Dropped when it dies.
+    pub rewards: Vec<PickupKind>,
+    /// Sorted by `health_fraction`, highest first.
+    pub phases: Vec<BossPhase>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct BossPhase {
+    /// The phase starts once health drops to this fraction of max health. The first phase should
+    /// start at 1.0.
+    pub health_fraction: f32,
+    pub speed: f32,
+    /// Time between attacks.
+    pub attack_cooldown: f32,
+    /// How long the boss stands still and glows before each attack.
+    pub windup: f32,
+    /// Used in order, then starts over.
+    pub attacks: Vec<BossAttack>,
+}
+
+#[derive(Clone, Deserialize)]
+pub enum BossAttack {
+    /// Dashes at the player.
+    Charge {
+        speed: f32,
+        duration: f32,
+    },
+    /// Fires projectiles evenly spaced in a circle.
+    Ring {
+        count: u8,
+        speed: f32,
+        damage: f32,
+    },
+    /// Fires a fan of projectiles at the player. Spread is in degrees.
+    Fan {
+        count: u8,
+        spread: f32,
+        speed: f32,
+        damage: f32,
+    },
+    /// Calls in regular enemies around itself.
+    Summon {
+        kind: EnemyKind,
+        count: u8,
+    },
+}
+
+/// Tracks which bosses have shown up this run. Lives on the spawner.
+#[derive(Default, Component)]
+pub struct BossSpawner {
+    spawned: usize,
+}
+
+#[derive(Component)]
+pub struct Boss {
+    def: BossDef,
+    phase: usize,
+    next_attack: usize,
+    cooldown: Timer,
+    state: BossState,
+}
+
+enum BossState {
+    Chasing,
+    WindingUp {
+        attack: BossAttack,
+        timer: Timer,
+    },
+    Charging {
+        velocity: Vec2,
+        timer: Timer,
+    },
+}
+
+impl Boss {
+    fn new(def: BossDef) -> Self {
+        let cooldown = def.phases.first()
+            .map(|phase| phase.attack_cooldown)
+            .unwrap_or(1.0);
+        Self {
+            def,
+            phase: 0,
+            next_attack: 0,
+            cooldown: Timer::from_seconds(cooldown, TimerMode::Once),
+            state: BossState::Chasing,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.def.name
+    }
+
+    pub fn phase(&self) -> usize {
+        self.phase
+    }
+
+    fn current_phase(&self) -> Option<&BossPhase> {
+        self.def.phases.get(self.phase)
+    }
+}
+
+/// Keeps the player within a circle while a boss is alive.
+#[derive(Component)]
+pub struct Arena {
+    pub center: Vec2,
+    pub radius: f32,
+    boss: Entity,
+}
+
+pub fn spawn_boss(
+    def: &BossDef,
+    pos: Vec2,
+    commands: &mut Commands,
+    assets: &GameAssets,
+) -> Entity {
+    let groups = groups::ENEMY;
+    let masks = groups::WORLD | groups::ENEMY;
+    let collider = ColliderBundle::new(def.collider_size, Vec2::ZERO, groups, masks);
+    let collider = commands.spawn(collider)
+        .insert(Name::new("BossCollider"))
+        .id();
+
+    let groups = groups::HIT;
+    let masks = groups::PLAYER;
+    let hit_box = ColliderBundle::new(def.collider_size - 2.0, Vec2::ZERO, groups, masks);
+    let contact_hit = HitSpec::new(def.contact_damage)
+        .with_knockback(CONTACT_KNOCKBACK)
+        // Bumping into the player again always hurts.
+        .with_rehit_interval(0.0);
+    let hit_box = commands.spawn(hit_box)
+        .insert(contact_hit)
+        .insert(Name::new("BossHitBox"))
+        .id();
+
+    let hurt_box = HurtBoxBundle::new(def.collider_size, Vec2::ZERO, groups::ENEMY);
+    let hurt_box = commands.spawn(hurt_box)
+        .insert(Name::new("BossHurtBox"))
+        .id();
+
+    // No knockback, bosses stand their ground.
+    commands.spawn((
+        Enemy,
+        Boss::new(def.clone()),
+        Name::new(def.name.clone()),
+        SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some(Vec2::splat(def.size)),
+                ..default()
+            },
+            texture: assets.enemy.clone(),
+            transform: Transform::from_translation(pos.extend(BOSS_Z)),
+            ..default()
+        },
+        TextureAtlas {
+            layout: assets.enemy_atlas.clone(),
+            index: def.sprite.sprite_index(&assets.enemy_indices),
+        },
+        Facing { dir: Vec2::X },
+        EnemyHealth::new(def.health),
+        Interpolated::default(),
+        RunEntity,
+        RigidBody::Dynamic,
+        LockedAxes::ROTATION_LOCKED,
+        Velocity::default(),
+    ))
+        .add_child(collider)
+        .add_child(hit_box)
+        .add_child(hurt_box)
+        .id()
+}
+
+fn spawn_arena(
+    center: Vec2,
+    radius: f32,
+    boss: Entity,
+    commands: &mut Commands,
+    assets: &GameAssets,
+) {
+    commands.spawn((
+        Arena {
+            center,
+            radius,
+            boss,
+        },
+        Name::new("BossArena"),
+        SpatialBundle::from_transform(Transform::from_translation(center.extend(1.0))),
+        RunEntity,
+    )).with_children(|parent| {
+        for i in 0..ARENA_MARKERS {
+            let angle = i as f32 / ARENA_MARKERS as f32 * std::f32::consts::TAU;
+            let offset = Mat2::from_angle(angle) * Vec2::X * radius;
+            parent.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: ARENA_MARKER_COLOR,
+                        ..default()
+                    },
+                    texture: assets.projectiles.clone(),
+                    transform: Transform::from_translation(offset.extend(0.0)),
+                    ..default()
+                },
+                TextureAtlas {
+                    layout: assets.projectile_atlas.clone(),
+                    index: assets.projectile_indices.sparkle,
+                },
+            ));
+        }
+    });
+}
+
+fn no_boss_alive(
+    boss_q: Query<(), With<Boss>>,
+) -> bool {
+    boss_q.is_empty()
+}
+
+fn spawn_bosses(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    bosses_configs: Res<Assets<BossesConfig>>,
+    game_timers: Res<GameTimers>,
+    mut rng: ResMut<GameRng>,
+    mut spawner_q: Query<&mut BossSpawner>,
+    player_q: Query<&Transform, With<Player>>,
+) {
+    let Ok(mut spawner) = spawner_q.get_single_mut() else {
+        return;
+    };
+    let Some(bosses) = bosses_configs.get(&assets.bosses) else {
+        return;
+    };
+    let Some(def) = bosses.bosses.get(spawner.spawned) else {
+        return;
+    };
+    if game_timers.game_time.elapsed_secs() < def.spawn_time {
+        return;
+    }
+    let Ok(player_transform) = player_q.get_single() else {
+        return;
+    };
+
+    info!("Spawning boss {}!", def.name);
+    spawner.spawned += 1;
+
+    let center = player_transform.translation.truncate();
+    let angle = rng.spawning.f32() * std::f32::consts::TAU;
+    let offset = Mat2::from_angle(angle) * Vec2::X * BOSS_SPAWN_DISTANCE.min(def.arena_radius * 0.75);
+    let boss = spawn_boss(def, center + offset, &mut commands, &assets);
+    spawn_arena(center, def.arena_radius, boss, &mut commands, &assets);
+}
+
+fn update_boss_phase(
+    mut boss_q: Query<(&mut Boss, &EnemyHealth)>,
+) {
+    for (mut boss, health) in boss_q.iter_mut() {
+        let fraction = health.current / health.max;
+        let phase = boss.def.phases.iter()
+            .rposition(|phase| fraction <= phase.health_fraction)
+            .unwrap_or(0);
+        if phase > boss.phase {
+            info!("{} entered phase {}", boss.def.name, phase + 1);
+            boss.phase = phase;
+            boss.next_attack = 0;
+        }
+    }
+}
+
+fn boss_ai(
+    mut commands: Commands,
+    time: Res<Time>,
+    assets: Res<GameAssets>,
+    player_q: Query<&Transform, With<Player>>,
+    mut boss_q: Query<(&mut Boss, &mut Velocity, &mut Facing, &mut Sprite, &Transform), Without<Death>>,
+) {
+    let Ok(player_transform) = player_q.get_single() else {
+        return;
+    };
+
+    for (mut boss, mut velocity, mut facing, mut sprite, transform) in boss_q.iter_mut() {
+        let boss = &mut *boss;
+        let Some(phase) = boss.current_phase().cloned() else {
+            continue;
+        };
+
+        let pos = transform.translation.truncate();
+        let dir = (player_transform.translation.truncate() - pos).normalize_or_zero();
+
+        match &mut boss.state {
+            BossState::Chasing => {
+                velocity.linvel = dir * phase.speed;
+                facing.dir = dir;
+
+                boss.cooldown.tick(time.delta());
+                if boss.cooldown.finished() && !phase.attacks.is_empty() {
+                    let attack = phase.attacks[boss.next_attack % phase.attacks.len()].clone();
+                    boss.next_attack += 1;
+                    boss.state = BossState::WindingUp {
+                        attack,
+                        timer: Timer::from_seconds(phase.windup, TimerMode::Once),
+                    };
+                    sprite.color = BOSS_WINDUP_COLOR;
+                    velocity.linvel = Vec2::ZERO;
+                }
+            }
+            BossState::WindingUp { attack, timer } => {
+                velocity.linvel = Vec2::ZERO;
+                facing.dir = dir;
+
+                timer.tick(time.delta());
+                if !timer.finished() {
+                    continue;
+                }
+
+                sprite.color = Color::WHITE;
+                let attack = attack.clone();
+                boss.state = perform_attack(&attack, pos, dir, &mut commands, &assets);
+                boss.cooldown.set_duration(Duration::from_secs_f32(phase.attack_cooldown));
+                boss.cooldown.reset();
+            }
+            BossState::Charging { velocity: charge_velocity, timer } => {
+                velocity.linvel = *charge_velocity;
+
+                timer.tick(time.delta());
+                if timer.finished() {
+                    boss.state = BossState::Chasing;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the state the boss is in afterwards.
+fn perform_attack(
+    attack: &BossAttack,
+    pos: Vec2,
+    dir: Vec2,
+    commands: &mut Commands,
+    assets: &GameAssets,
+) -> BossState {
+    match attack {
+        BossAttack::Charge { speed, duration } => {
+            return BossState::Charging {
+                velocity: dir * *speed,
+                timer: Timer::from_seconds(*duration, TimerMode::Once),
+            };
+        }
+        BossAttack::Ring { count, speed, damage } => {
+            for i in 0..*count {
+                let angle = i as f32 / *count as f32 * std::f32::consts::TAU;
+                let fire_dir = Mat2::from_angle(angle) * dir;
+                enemies::spawn_hostile_projectile(pos, fire_dir * *speed, *damage, commands, assets);
+            }
+        }
+        BossAttack::Fan { count, spread, speed, damage } => {
+            let spread = spread.to_radians();
+            for i in 0..*count {
+                let t = if *count > 1 { i as f32 / (*count - 1) as f32 - 0.5 } else { 0.0 };
+                let fire_dir = Mat2::from_angle(t * spread) * dir;
+                enemies::spawn_hostile_projectile(pos, fire_dir * *speed, *damage, commands, assets);
+            }
+        }
+        BossAttack::Summon { kind, count } => {
+            for i in 0..*count {
+                let angle = i as f32 / *count as f32 * std::f32::consts::TAU;
+                let offset = Mat2::from_angle(angle) * Vec2::X * 30.0;
+                enemies::spawn_enemy(*kind, pos + offset, commands, assets);
+            }
+        }
+    }
+    BossState::Chasing
+}
+
+fn lock_player_in_arena(
+    arena_q: Query<&Arena>,
+    mut player_q: Query<(&mut Transform, &mut Velocity), With<Player>>,
+) {
+    let Ok(arena) = arena_q.get_single() else {
+        return;
+    };
+
+    for (mut transform, mut velocity) in player_q.iter_mut() {
+        let offset = transform.translation.truncate() - arena.center;
+        if offset.length() < arena.radius {
+            continue;
+        }
+
+        // Put them back on the edge and stop them from moving any further out.
+        let normal = offset.normalize_or_zero();
+        let pos = arena.center + normal * arena.radius;
+        transform.translation = pos.extend(transform.translation.z);
+        let outward = velocity.linvel.dot(normal);
+        if outward > 0.0 {
+            velocity.linvel -= normal * outward;
+        }
+    }
+}
+
+fn drop_boss_rewards(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    mut rng: ResMut<GameRng>,
+    boss_q: Query<(Entity, &Boss, &GlobalTransform), Added<Death>>,
+    arena_q: Query<(Entity, &Arena)>,
+) {
+    for (entity, boss, transform) in boss_q.iter() {
+        info!("Defeated boss {}!", boss.def.name);
+
+        let pos = transform.translation().truncate();
+        pickups::spawn_drops(boss.def.rewards.iter().copied(), pos, &mut rng.drops, &mut commands, &assets);
+
+        for (arena_entity, arena) in arena_q.iter() {
+            if arena.boss == entity {
+                commands.entity(arena_entity).despawn_recursive();
+            }
+        }
+    }
+}
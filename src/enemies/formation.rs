@@ -0,0 +1,230 @@
+use bevy::prelude::*;
+use bevy::math::Mat2;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    assets::GameAssets,
+    combat::Knockback,
+    enemies::{self, EnemyKind},
+    game::RunEntity,
+    status::StatusEffects,
+};
+
+/// How long the warning marker shows before enemies appear.
+const WARNING_SECS: f32 = 0.8;
+const WARNING_BLINKS: f32 = 4.0;
+const WARNING_COLOR: Color = Color::srgba(1.0, 0.2, 0.2, 0.8);
+const WARNING_Z: f32 = 1.0;
+/// Index of the crosshair used as the warning marker.
+const WARNING_SPRITE: usize = 4;
+
+/// How fast enemies in a line move across before going after the player.
+const LINE_SWEEP_SPEED: f32 = 90.0;
+
+/// How a group of enemies is placed around the player.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum Formation {
+    /// One enemy at a random spot around the player.
+    Single,
+    /// Surrounds the player, then closes in.
+    Ring {
+        count: u32,
+    },
+    /// A group all coming from one direction.
+    Cluster {
+        count: u32,
+        /// How far from the middle of the group enemies can be.
+        spread: f32,
+    },
+    /// A row off to one side that sweeps across the player's path before going after them.
+    Line {
+        count: u32,
+        spacing: f32,
+    },
+    /// Pops up ahead of where the player is going.
+    Ambush {
+        count: u32,
+        /// How far ahead of the player.
+        distance: f32,
+    },
+}
+
+/// Where an enemy in a formation spawns, and how it moves at first.
+pub struct FormationSpot {
+    pub pos: Vec2,
+    pub sweep: Option<Sweep>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Sweep {
+    velocity: Vec2,
+    secs: f32,
+}
+
+impl Formation {
+    pub fn count(&self) -> u32 {
+        match self {
+            Self::Single => 1,
+            Self::Ring { count }
+                | Self::Cluster { count, .. }
+                | Self::Line { count, .. }
+                | Self::Ambush { count, .. } => *count,
+        }
+    }
+
+    /// `heading` is the direction the player is moving in, or facing if they're standing still.
+    /// `spawn_distance` is how far from the player to spawn in a given direction.
+    pub fn spots(
+        &self,
+        player_pos: Vec2,
+        heading: Vec2,
+        spawn_distance: impl Fn(Vec2) -> f32,
+        rng: &mut fastrand::Rng,
+    ) -> Vec<FormationSpot> {
+        let random_dir = |rng: &mut fastrand::Rng| Mat2::from_angle(rng.f32() * std::f32::consts::TAU) * Vec2::X;
+        let spot = |pos| FormationSpot { pos, sweep: None };
+
+        match *self {
+            Self::Single => {
+                let dir = random_dir(rng);
+                vec![spot(player_pos + dir * spawn_distance(dir))]
+            }
+            Self::Ring { count } => {
+                let start = rng.f32() * std::f32::consts::TAU;
+                (0..count)
+                    .map(|i| {
+                        let angle = start + i as f32 / count as f32 * std::f32::consts::TAU;
+                        let dir = Mat2::from_angle(angle) * Vec2::X;
+                        spot(player_pos + dir * spawn_distance(dir))
+                    })
+                    .collect()
+            }
+            Self::Cluster { count, spread } => {
+                let dir = random_dir(rng);
+                let center = player_pos + dir * spawn_distance(dir);
+                (0..count)
+                    .map(|_| spot(center + random_dir(rng) * spread * rng.f32().sqrt()))
+                    .collect()
+            }
+            Self::Line { count, spacing } => {
+                // Start off to one side and sweep across to the other.
+                let side = random_dir(rng);
+                let along = side.perp();
+                let distance = spawn_distance(side);
+                let center = player_pos + side * distance;
+                let half_length = (count.saturating_sub(1)) as f32 * spacing / 2.0;
+                (0..count)
+                    .map(|i| FormationSpot {
+                        pos: center + along * (i as f32 * spacing - half_length),
+                        // Long enough to cross to the other side of the player.
+                        sweep: Some(Sweep {
+                            velocity: -side * LINE_SWEEP_SPEED,
+                            secs: 2.0 * distance / LINE_SWEEP_SPEED,
+                        }),
+                    })
+                    .collect()
+            }
+            Self::Ambush { count, distance: ahead } => {
+                let heading = heading.try_normalize().unwrap_or_else(|| random_dir(rng));
+                let center = player_pos + heading * ahead;
+                // Spread out in an arc across the player's path.
+                (0..count)
+                    .map(|i| {
+                        let t = if count > 1 { i as f32 / (count - 1) as f32 - 0.5 } else { 0.0 };
+                        let dir = Mat2::from_angle(t * std::f32::consts::FRAC_PI_2) * heading;
+                        spot(center + dir * 20.0)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Shows where an enemy is about to spawn, and spawns it once the timer runs out.
+#[derive(Component)]
+pub struct SpawnWarning {
+    kind: EnemyKind,
+    sweep: Option<Sweep>,
+    timer: Timer,
+}
+
+/// Moves in a straight line for a while, ignoring the rest of its AI.
+#[derive(Component)]
+pub struct AiSweep {
+    velocity: Vec2,
+    timer: Timer,
+}
+
+pub fn spawn_warning(
+    kind: EnemyKind,
+    spot: FormationSpot,
+    commands: &mut Commands,
+    assets: &GameAssets,
+) {
+    commands.spawn((
+        SpawnWarning {
+            kind,
+            sweep: spot.sweep,
+            timer: Timer::from_seconds(WARNING_SECS, TimerMode::Once),
+        },
+        Name::new("SpawnWarning"),
+        SpriteBundle {
+            sprite: Sprite {
+                color: WARNING_COLOR,
+                ..default()
+            },
+            texture: assets.crosshairs.clone(),
+            transform: Transform::from_translation(spot.pos.extend(WARNING_Z)),
+            ..default()
+        },
+        TextureAtlas {
+            layout: assets.crosshairs_atlas.clone(),
+            index: WARNING_SPRITE,
+        },
+        RunEntity,
+    ));
+}
+
+pub fn hatch_spawn_warnings(
+    mut commands: Commands,
+    time: Res<Time>,
+    assets: Res<GameAssets>,
+    mut warning_q: Query<(Entity, &mut SpawnWarning, &mut Visibility, &Transform)>,
+) {
+    for (entity, mut warning, mut visibility, transform) in warning_q.iter_mut() {
+        warning.timer.tick(time.delta());
+
+        let blink = (warning.timer.fraction() * WARNING_BLINKS * 2.0) as u32 % 2 == 0;
+        *visibility = if blink { Visibility::Inherited } else { Visibility::Hidden };
+
+        if !warning.timer.finished() {
+            continue;
+        }
+
+        commands.entity(entity).despawn_recursive();
+        let pos = transform.translation.truncate();
+        let enemy = enemies::spawn_enemy(warning.kind, pos, &mut commands, &assets);
+        if let Some(sweep) = warning.sweep {
+            commands.entity(enemy).insert(AiSweep {
+                velocity: sweep.velocity,
+                timer: Timer::from_seconds(sweep.secs, TimerMode::Once),
+            });
+        }
+    }
+}
+
+pub fn sweep_ai(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut ai_q: Query<(Entity, &mut AiSweep, &mut Velocity, &Knockback, &StatusEffects)>,
+) {
+    for (entity, mut ai, mut velocity, knockback, status) in ai_q.iter_mut() {
+        ai.timer.tick(time.delta());
+        if ai.timer.finished() {
+            commands.entity(entity).remove::<AiSweep>();
+        } else if !knockback.is_active() {
+            velocity.linvel = ai.velocity * status.speed_multiplier();
+        }
+    }
+}
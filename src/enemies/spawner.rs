@@ -1,18 +1,27 @@
 use std::time::Duration;
 
 use bevy::prelude::*;
+use bevy::ecs::system::SystemParam;
 use bevy::math::Mat2;
+use bevy::reflect::TypePath;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
 
 use crate::{
-    AppState,
     assets::GameAssets,
-    game::GameTimers,
-    enemies,
+    game::{CameraView, Facing, GameLogicSet, GameTimers},
+    enemies::{self, boss::Boss, formation::{self, Formation, SpawnWarning}, Death, Enemy, EnemyKind},
     player::Player,
+    rng::{self, GameRng},
 };
 
-/// How far from the player should enemies spawn.
-const SPAWN_DISTANCE: f32 = 300.0;
+/// How far past the edge of the screen should enemies spawn.
+const SPAWN_MARGIN: f32 = 24.0;
+/// Enemies this many times further from the player than the edge of the screen get moved back in
+/// front of them.
+const RECYCLE_DISTANCE: f32 = 2.0;
+/// How far to either side of the player's heading recycled enemies can end up, in radians.
+const RECYCLE_SPREAD: f32 = std::f32::consts::FRAC_PI_3;
 
 pub struct SpawnerPlugin;
 
@@ -20,35 +29,162 @@ impl Plugin for SpawnerPlugin {
     fn build(&self, app: &mut App) {
         app
             .init_resource::<EnemyCount>()
-            .add_systems(Update, (
-                spawn_enemies,
-                increase_difficulty,
-            ).run_if(in_state(AppState::InGame)))
-            .add_systems(Update, update_enemy_count.run_if(in_state(AppState::InGame)).before(enemies::despawn_dead_enemies));
+            .add_event::<WaveAnnouncement>()
+            .add_systems(FixedUpdate, (
+                apply_wave_schedule,
+                run_wave_events.after(apply_wave_schedule),
+                spawn_enemies.after(run_wave_events),
+                formation::hatch_spawn_warnings,
+                formation::sweep_ai
+                    .after(enemies::follow_player_ai)
+                    .after(enemies::zig_zag_ai)
+                    .after(enemies::ranged_ai),
+                recycle_far_enemies,
+                update_enemy_count.before(enemies::despawn_dead_enemies),
+            ).in_set(GameLogicSet))
+            .add_systems(Update, skip_past_wave_events_on_reload);
     }
 }
 
 #[derive(Default, Resource, Reflect)]
 pub struct EnemyCount(pub u32);
 
+/// How the spawner's difficulty changes over a run.
+#[derive(Deserialize, Asset, TypePath)]
+pub struct WaveSchedule {
+    /// Sorted by time.
+    pub waves: Vec<Wave>,
+    /// Sorted by time.
+    #[serde(default)]
+    pub events: Vec<TimedWaveEvent>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Wave {
+    /// Survival time in seconds this wave holds steady for. Between waves, the spawner blends
+    /// from one to the next.
+    pub time: (f32, f32),
+    pub max_enemies: u32,
+    /// Seconds between spawns.
+    pub spawn_rate: f32,
+    /// Which kinds of enemies to spawn, and how likely each is relative to the others.
+    pub enemy_weights: Vec<(EnemyKind, f32)>,
+    /// How spawned enemies are grouped, and how likely each formation is relative to the others.
+    /// Enemies spawn one at a time if there are none.
+    #[serde(default)]
+    pub formations: Vec<(Formation, f32)>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TimedWaveEvent {
+    /// Survival time in seconds when it happens.
+    pub time: f32,
+    pub event: WaveEvent,
+}
+
+#[derive(Clone, Deserialize)]
+pub enum WaveEvent {
+    /// Spawns a group of enemies all at once, even past the max.
+    Burst {
+        kind: EnemyKind,
+        formation: Formation,
+    },
+    /// Shows a message on screen.
+    Announce(String),
+    /// Stops spawning for a while, to give the player a breather.
+    Calm {
+        duration: f32,
+    },
+}
+
+/// Sent when the wave schedule has something to tell the player.
+#[derive(Event)]
+pub struct WaveAnnouncement(pub String);
+
+impl WaveSchedule {
+    /// The difficulty at the given survival time.
+    pub fn sample(&self, time: f32) -> Option<Wave> {
+        let next = self.waves.iter().position(|wave| time < wave.time.1)?;
+        let wave = &self.waves[next];
+        if next == 0 || time >= wave.time.0 {
+            return Some(wave.clone());
+        }
+
+        // Between the end of the last wave and the start of this one.
+        let prev = &self.waves[next - 1];
+        let gap = wave.time.0 - prev.time.1;
+        let t = if gap > 0.0 { ((time - prev.time.1) / gap).clamp(0.0, 1.0) } else { 1.0 };
+
+        let mut enemy_weights = prev.enemy_weights.clone();
+        for &(kind, _) in wave.enemy_weights.iter() {
+            if !enemy_weights.iter().any(|(other, _)| *other == kind) {
+                enemy_weights.push((kind, 0.0));
+            }
+        }
+        for (kind, weight) in enemy_weights.iter_mut() {
+            *weight = weight.lerp(wave.weight(*kind), t);
+        }
+
+        // Formations can't be blended, so switch over halfway.
+        let formations = if t < 0.5 { &prev.formations } else { &wave.formations };
+
+        Some(Wave {
+            time: (time, time),
+            max_enemies: (prev.max_enemies as f32).lerp(wave.max_enemies as f32, t).round() as u32,
+            spawn_rate: prev.spawn_rate.lerp(wave.spawn_rate, t),
+            enemy_weights,
+            formations: formations.clone(),
+        })
+    }
+
+    fn last_wave(&self) -> Option<&Wave> {
+        self.waves.last()
+    }
+}
+
+impl Wave {
+    fn weight(&self, kind: EnemyKind) -> f32 {
+        self.enemy_weights.iter()
+            .find(|(other, _)| *other == kind)
+            .map(|(_, weight)| *weight)
+            .unwrap_or(0.0)
+    }
+}
+
 #[derive(Component)]
 pub struct Spawner {
     pub max_enemies: u32,
-    // Enemies per second.
+    /// Seconds between spawns.
     pub spawn_rate: f32,
     pub cooldown: Timer,
+    /// Which kinds of enemies to spawn, and how likely each is relative to the others.
+    pub enemy_weights: Vec<(EnemyKind, f32)>,
+    /// How spawned enemies are grouped, and how likely each formation is relative to the others.
+    pub formations: Vec<(Formation, f32)>,
+    /// Turned off by debug tools, so difficulty set by hand sticks.
+    pub follow_schedule: bool,
+    /// Index of the next event in the wave schedule.
+    next_event: usize,
+    calm: Timer,
 }
 
 impl Spawner {
     pub fn new(max_enemies: u32, spawn_rate: f32) -> Self {
+        let mut calm = Timer::from_seconds(0.0, TimerMode::Once);
+        calm.tick(Duration::ZERO);
         Self {
             max_enemies,
             spawn_rate,
             cooldown: Timer::from_seconds(spawn_rate, TimerMode::Repeating),
+            enemy_weights: vec![(EnemyKind::Rat, 1.0)],
+            formations: Vec::new(),
+            follow_schedule: true,
+            next_event: 0,
+            calm,
         }
     }
 
-    fn set_difficulty(&mut self, max_enemies: u32, spawn_rate: f32) {
+    pub fn set_difficulty(&mut self, max_enemies: u32, spawn_rate: f32) {
         self.max_enemies = max_enemies;
         self.spawn_rate = spawn_rate;
         self.cooldown.set_duration(Duration::from_secs_f32(spawn_rate));
@@ -61,50 +197,216 @@ impl Spawner {
             self.cooldown.pause();
         }
     }
+
+    pub fn is_calm(&self) -> bool {
+        !self.calm.finished()
+    }
+
+    /// Picks a kind of enemy to spawn based on their weights.
+    pub fn pick_kind(&self, rng: &mut fastrand::Rng) -> EnemyKind {
+        rng::pick_weighted(&self.enemy_weights, rng).unwrap_or(EnemyKind::Rat)
+    }
+
+    /// Picks how to group the next spawn based on their weights.
+    pub fn pick_formation(&self, rng: &mut fastrand::Rng) -> Formation {
+        rng::pick_weighted(&self.formations, rng).unwrap_or(Formation::Single)
+    }
 }
 
-fn increase_difficulty(
+fn apply_wave_schedule(
+    assets: Res<GameAssets>,
+    schedules: Res<Assets<WaveSchedule>>,
     game_timers: Res<GameTimers>,
     mut spawner_q: Query<&mut Spawner>,
 ) {
-    if let Ok(mut spawner) = spawner_q.get_single_mut() {
-        if spawner.max_enemies < 300 && game_timers.game_time.elapsed_secs() > 300.0 {
-            spawner.set_difficulty(300, 0.1);
-        } else if spawner.max_enemies < 150 && game_timers.game_time.elapsed_secs() > 120.0 {
-            spawner.set_difficulty(150, 0.3);
-        } else if spawner.max_enemies < 100 && game_timers.game_time.elapsed_secs() > 60.0 {
-            spawner.set_difficulty(100, 0.5);
-        } else {
-            spawner.set_difficulty(50, 1.0);
+    let Some(schedule) = schedules.get(&assets.waves) else {
+        return;
+    };
+    let Ok(mut spawner) = spawner_q.get_single_mut() else {
+        return;
+    };
+    if !spawner.follow_schedule {
+        return;
+    }
+
+    let time = game_timers.game_time.elapsed_secs();
+    let Some(wave) = schedule.sample(time).or_else(|| schedule.last_wave().cloned()) else {
+        return;
+    };
+    if spawner.max_enemies != wave.max_enemies || spawner.spawn_rate != wave.spawn_rate {
+        spawner.set_difficulty(wave.max_enemies, wave.spawn_rate);
+    }
+    spawner.enemy_weights = wave.enemy_weights;
+    spawner.formations = wave.formations;
+}
+
+fn run_wave_events(
+    mut formations: FormationSpawner,
+    schedules: Res<Assets<WaveSchedule>>,
+    game_timers: Res<GameTimers>,
+    time: Res<Time>,
+    mut rng: ResMut<GameRng>,
+    mut announcements: EventWriter<WaveAnnouncement>,
+    mut spawner_q: Query<&mut Spawner>,
+) {
+    let Some(schedule) = schedules.get(&formations.assets.waves) else {
+        return;
+    };
+    let Ok(mut spawner) = spawner_q.get_single_mut() else {
+        return;
+    };
+    spawner.calm.tick(time.delta());
+    if !spawner.follow_schedule {
+        return;
+    }
+
+    let game_time = game_timers.game_time.elapsed_secs();
+    while let Some(timed) = schedule.events.get(spawner.next_event) {
+        if timed.time > game_time {
+            break;
+        }
+        spawner.next_event += 1;
+
+        match &timed.event {
+            WaveEvent::Burst { kind, formation } => {
+                formations.spawn(*kind, formation, &mut rng.spawning);
+            }
+            WaveEvent::Announce(text) => {
+                announcements.send(WaveAnnouncement(text.clone()));
+            }
+            WaveEvent::Calm { duration } => {
+                spawner.calm = Timer::from_seconds(*duration, TimerMode::Once);
+            }
         }
     }
 }
 
-fn spawn_enemies(
-    mut commands: Commands,
+/// Events may have moved around when the schedule is hot reloaded, so only run the ones still
+/// ahead of us.
+fn skip_past_wave_events_on_reload(
     assets: Res<GameAssets>,
+    schedules: Res<Assets<WaveSchedule>>,
+    game_timers: Res<GameTimers>,
+    mut schedule_events: EventReader<AssetEvent<WaveSchedule>>,
+    mut spawner_q: Query<&mut Spawner>,
+) {
+    let modified = schedule_events.read()
+        .any(|event| event.is_modified(&assets.waves));
+    if !modified {
+        return;
+    }
+    let Some(schedule) = schedules.get(&assets.waves) else {
+        return;
+    };
+
+    info!("Wave schedule changed, updating spawner.");
+    let game_time = game_timers.game_time.elapsed_secs();
+    for mut spawner in spawner_q.iter_mut() {
+        spawner.next_event = schedule.events.iter()
+            .take_while(|timed| timed.time <= game_time)
+            .count();
+    }
+}
+
+/// Where the player is and which way they're headed.
+type PlayerHeading = (&'static Transform, &'static Velocity, &'static Facing);
+
+/// Enemies that can be moved back in front of the player. Bosses stay in their arenas.
+type RecyclableEnemy = (With<Enemy>, Without<Player>, Without<Boss>, Without<Death>);
+
+/// Everything needed to place a formation around the player.
+#[derive(SystemParam)]
+struct FormationSpawner<'w, 's> {
+    commands: Commands<'w, 's>,
+    assets: Res<'w, GameAssets>,
+    camera_view: Res<'w, CameraView>,
+    player_q: Query<'w, 's, PlayerHeading, With<Player>>,
+}
+
+impl FormationSpawner<'_, '_> {
+    fn has_player(&self) -> bool {
+        !self.player_q.is_empty()
+    }
+
+    /// Shows warnings where the enemies in the formation are about to spawn.
+    fn spawn(&mut self, kind: EnemyKind, formation: &Formation, rng: &mut fastrand::Rng) {
+        let Ok((player_transform, player_velocity, player_facing)) = self.player_q.get_single() else {
+            return;
+        };
+        let player_pos = player_transform.translation.truncate();
+        let heading = player_velocity.linvel.try_normalize().unwrap_or(player_facing.dir);
+        let camera_view = &self.camera_view;
+        let spawn_distance = |dir| spawn_distance(camera_view, dir);
+        for spot in formation.spots(player_pos, heading, spawn_distance, rng) {
+            formation::spawn_warning(kind, spot, &mut self.commands, &self.assets);
+        }
+    }
+}
+
+/// Just off screen in the given direction from the player, since the camera follows them.
+fn spawn_distance(camera_view: &CameraView, dir: Vec2) -> f32 {
+    camera_view.edge_distance(dir) + SPAWN_MARGIN
+}
+
+fn spawn_enemies(
+    mut formations: FormationSpawner,
     time: Res<Time>,
+    mut rng: ResMut<GameRng>,
     enemy_count: Res<EnemyCount>,
     mut spawner_q: Query<&mut Spawner>,
-    player_q: Query<&Transform, With<Player>>,
+    warning_q: Query<(), With<SpawnWarning>>,
 ) {
     if let Ok(mut spawner) = spawner_q.get_single_mut() {
         spawner.cooldown.tick(time.delta());
-        if (enemy_count.0 < spawner.max_enemies) && spawner.cooldown.just_finished() {
-            trace!("Spawning a basic enemy!");
-
-            // TODO: Handle case where player doesn't exist.
-            let player_pos = player_q.single().translation.truncate();
-            // Pick a position randomly on the radius of a circle SPAWN_DISTANCE from the player.
-            let angle = fastrand::f32() * std::f32::consts::TAU;
-            let rot_matrix = Mat2::from_angle(angle);
-            let offset = rot_matrix * Vec2::X * SPAWN_DISTANCE;
-            let pos = player_pos + offset;
-            enemies::spawn_basic_enemy(pos, &mut commands, &assets);
+        // Enemies about to spawn count toward the max too.
+        let room = spawner.max_enemies.saturating_sub(enemy_count.0 + warning_q.iter().count() as u32);
+        if room > 0 && !spawner.is_calm() && spawner.cooldown.just_finished() {
+            if !formations.has_player() {
+                return;
+            }
+            let kind = spawner.pick_kind(&mut rng.spawning);
+            let mut formation = spawner.pick_formation(&mut rng.spawning);
+            if formation.count() > room {
+                formation = Formation::Single;
+            }
+            trace!("Spawning {:?} of {}!", formation, kind.name());
+            formations.spawn(kind, &formation, &mut rng.spawning);
         }
     }
 }
 
+/// Instead of leaving enemies the player ran away from to wander around, move them back in front
+/// of the player so they keep up the pressure.
+fn recycle_far_enemies(
+    camera_view: Res<CameraView>,
+    mut rng: ResMut<GameRng>,
+    player_q: Query<PlayerHeading, With<Player>>,
+    mut enemy_q: Query<&mut Transform, RecyclableEnemy>,
+) {
+    let Ok((player_transform, player_velocity, player_facing)) = player_q.get_single() else {
+        return;
+    };
+    let player_pos = player_transform.translation.truncate();
+    let heading = player_velocity.linvel.try_normalize().unwrap_or(player_facing.dir);
+    let recycle_view = CameraView {
+        half_size: camera_view.half_size * RECYCLE_DISTANCE,
+    };
+
+    for mut transform in enemy_q.iter_mut() {
+        let offset = transform.translation.truncate() - player_pos;
+        if recycle_view.contains(offset) {
+            continue;
+        }
+
+        let angle = (rng.spawning.f32() * 2.0 - 1.0) * RECYCLE_SPREAD;
+        let dir = Mat2::from_angle(angle) * heading;
+        let pos = player_pos + dir * spawn_distance(&camera_view, dir);
+        trace!("Recycling enemy from {} to {}", transform.translation.truncate(), pos);
+        transform.translation.x = pos.x;
+        transform.translation.y = pos.y;
+    }
+}
+
 fn update_enemy_count(
     mut enemy_count: ResMut<EnemyCount>,
     spawned_q: Query<(), Added<enemies::Enemy>>,
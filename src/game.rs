@@ -1,16 +1,28 @@
 use bevy::prelude::*;
+use bevy::ecs::system::SystemParam;
 use bevy::time::Stopwatch;
+use bevy::transform::TransformSystem;
+use bevy::window::{PrimaryWindow, WindowResolution};
 use bevy_kira_audio::prelude::*;
+use bevy_rapier2d::plugin::PhysicsSet;
 
 use crate::{
-    AppState,
+    AppState, InRun,
     assets::{AudioAssets, AudioConfig, GameAssets},
     combat,
-    enemies,
+    damage_numbers,
+    enemies::{self, spawner::EnemyCount},
+    game_feel,
     health::PlayerHealth,
+    interpolation::InterpolationSet,
+    pickups,
     player,
+    rng::{self, GameRng},
+    settings::Settings,
+    stats::{self, RunStats},
+    status,
     terrain,
-    ui,
+    upgrades,
     weapons,
     window::WindowState,
 };
@@ -22,28 +34,63 @@ impl Plugin for GamePlugin {
          app
             .add_plugins((
                 combat::CombatPlugin,
+                damage_numbers::DamageNumbersPlugin,
+                game_feel::GameFeelPlugin,
                 enemies::EnemiesPlugin,
+                pickups::PickupsPlugin,
                 player::PlayerPlugin,
+                stats::StatsPlugin,
+                status::StatusPlugin,
                 terrain::TerrainPlugin,
-                ui::UiPlugin,
+                upgrades::UpgradesPlugin,
             ))
             .register_type::<Facing>()
             .register_type::<PlayerHealth>()
+            .init_resource::<Settings>()
             .init_resource::<GameTimers>()
+            .init_resource::<CameraView>()
             .init_resource::<Bgm>()
-            .add_systems(OnEnter(AppState::InGame), setup_game)
-            .add_systems(Update, (
-                reset_game,
+            .add_event::<RunStarted>()
+            .insert_resource(GameRng::from_args_or_random())
+            .configure_sets(FixedUpdate, GameLogicSet
+                .run_if(in_state(InRun))
+                .before(PhysicsSet::SyncBackend))
+            .add_systems(Startup, spawn_camera)
+            .add_systems(PreUpdate, update_camera_view)
+            // A run starts when entering the game from the main menu or game over screen, and
+            // ends when leaving the game over screen or quitting from the pause menu.
+            .add_systems(OnTransition { exited: AppState::MainMenu, entered: AppState::InGame }, (setup_game, start_bgm))
+            .add_systems(OnTransition { exited: AppState::GameOver, entered: AppState::InGame }, (setup_game, start_bgm))
+            .add_systems(OnExit(AppState::GameOver), teardown_game)
+            .add_systems(OnTransition { exited: AppState::Paused, entered: AppState::MainMenu }, teardown_game)
+            .add_systems(OnEnter(AppState::Paused), pause_game)
+            .add_systems(OnExit(AppState::Paused), unpause_game)
+            .add_systems(FixedUpdate, (
                 tick_game_timers,
-            ).run_if(in_state(AppState::InGame)))
-            .add_systems(PostUpdate, (
-                update_sprite_facing,
                 update_lifetimes,
-            ).run_if(in_state(AppState::InGame)))
-            .add_systems(Update, camera_follows_player.run_if(in_state(AppState::InGame)));
+            ).in_set(GameLogicSet))
+            .add_systems(PostUpdate, update_sprite_facing.run_if(in_state(InRun)))
+            .add_systems(PostUpdate, camera_follows_player
+                .run_if(in_state(InRun))
+                .after(InterpolationSet)
+                .before(TransformSystem::TransformPropagate));
     }
 }
 
+/// Sent whenever a new run starts, after the world has been set up for it.
+#[derive(Event)]
+pub struct RunStarted;
+
+/// Game logic that runs in `FixedUpdate` at `GAME_LOGIC_FPS`, before the physics step. Only
+/// runs during a run, and game over doesn't stop it so enemies keep moving behind the game over
+/// screen.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GameLogicSet;
+
+/// Marks top-level entities that belong to the current run, so they get despawned when it ends.
+#[derive(Default, Component)]
+pub struct RunEntity;
+
 #[derive(Resource)]
 pub struct GameTimers {
     pub game_time: Stopwatch,
@@ -64,6 +111,27 @@ impl Default for GameTimers {
     }
 }
 
+/// How much of the world the camera shows, so game logic can tell what's on screen.
+#[derive(Default, Resource)]
+pub struct CameraView {
+    /// Half the width and height of the visible area, in world units.
+    pub half_size: Vec2,
+}
+
+impl CameraView {
+    /// How far from the center of the view its edge is in the given direction.
+    pub fn edge_distance(&self, dir: Vec2) -> f32 {
+        let x = if dir.x != 0.0 { self.half_size.x / dir.x.abs() } else { f32::INFINITY };
+        let y = if dir.y != 0.0 { self.half_size.y / dir.y.abs() } else { f32::INFINITY };
+        x.min(y)
+    }
+
+    /// Whether an offset from the center of the view is inside it.
+    pub fn contains(&self, offset: Vec2) -> bool {
+        offset.x.abs() <= self.half_size.x && offset.y.abs() <= self.half_size.y
+    }
+}
+
 #[derive(Default, Resource)]
 pub struct Bgm {
     pub handle: Handle<AudioInstance>,
@@ -77,36 +145,69 @@ fn tick_game_timers(
     game_timers.reset_time.tick(time.delta());
 }
 
-fn setup_game(
+fn spawn_camera(
     mut commands: Commands,
-    assets: Res<GameAssets>,
-    sounds: Res<AudioAssets>,
-    audio: Res<Audio>,
-    audio_config: Res<Assets<AudioConfig>>,
-    mut bgm: ResMut<Bgm>,
     window_state: Res<WindowState>,
-    mut game_timers: ResMut<GameTimers>,
-    mut spawned_chunks: ResMut<terrain::SpawnedChunks>,
 ) {
-    game_timers.game_time.reset();
-    game_timers.game_time.unpause();
-
     let mut camera_bundle = Camera2dBundle::default();
     camera_bundle.projection.scale = 1.0 / window_state.scale as f32;
     commands.spawn(camera_bundle);
+}
 
-    player::spawn_player(Vec2::ZERO, &mut commands, &assets);
+/// The camera's projection is scaled by the window scale, so the visible area shrinks as it grows.
+/// Without a window (e.g. headless), assume the default window size. A minimized window has no size,
+/// so keep the last view until it comes back, or assume the default size if there isn't one yet.
+fn update_camera_view(
+    mut camera_view: ResMut<CameraView>,
+    window_state: Res<WindowState>,
+    window_q: Query<&Window, With<PrimaryWindow>>,
+) {
+    let window_size = match window_q.get_single() {
+        Ok(window) if window.width() > 0.0 && window.height() > 0.0 => window.size(),
+        Ok(_) if camera_view.half_size != Vec2::ZERO => return,
+        _ => WindowResolution::default().size(),
+    };
+    let half_size = window_size / window_state.scale.max(1) as f32 / 2.0;
+    if camera_view.half_size != half_size {
+        camera_view.half_size = half_size;
+    }
+}
 
-    // enemies::spawn_basic_enemy(Vec2::new(300.0, 0.0), &mut commands, &assets);
+fn setup_game(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    mut game_timers: ResMut<GameTimers>,
+    mut spawned_chunks: ResMut<terrain::SpawnedChunks>,
+    weapons_configs: Res<Assets<weapons::WeaponsConfig>>,
+    rng: Res<GameRng>,
+    mut run_started: EventWriter<RunStarted>,
+) {
+    info!("Starting run with seed {}", rng.seed());
+    run_started.send(RunStarted);
+
+    game_timers.game_time.unpause();
+
+    let weapons = weapons_configs.get(&assets.weapons)
+        .expect("Weapons config asset not loaded properly!");
+    player::spawn_player(Vec2::ZERO, &mut commands, &assets, weapons);
 
     commands.spawn((
         enemies::spawner::Spawner::new(50, 1.0),
+        enemies::boss::BossSpawner::default(),
         Name::new("Spawner"),
+        RunEntity,
     ));
 
     // Spawn initial terrain chunks.
-    terrain::spawn_missing_chunks(IVec2::ZERO, &mut commands, &assets, &mut spawned_chunks);
+    terrain::spawn_missing_chunks(IVec2::ZERO, &mut commands, &assets, &rng, &mut spawned_chunks);
+}
 
+fn start_bgm(
+    sounds: Res<AudioAssets>,
+    audio: Res<Audio>,
+    audio_config: Res<Assets<AudioConfig>>,
+    mut bgm: ResMut<Bgm>,
+) {
     let audio_config = audio_config.get(&sounds.config).unwrap();
     bgm.handle = audio.play(sounds.bgm.clone())
         .looped()
@@ -114,44 +215,79 @@ fn setup_game(
         .handle();
 }
 
-fn reset_game(
+/// Resources that only hold state for the current run.
+#[derive(SystemParam)]
+struct RunResources<'w> {
+    game_timers: ResMut<'w, GameTimers>,
+    enemy_count: ResMut<'w, EnemyCount>,
+    run_stats: ResMut<'w, RunStats>,
+    weapon_pool: ResMut<'w, weapons::WeaponPool>,
+    spawned_chunks: ResMut<'w, terrain::SpawnedChunks>,
+    camera_shake: ResMut<'w, game_feel::CameraShake>,
+    hit_stop: ResMut<'w, game_feel::HitStop>,
+}
+
+impl RunResources<'_> {
+    fn reset(&mut self) {
+        *self.game_timers = default();
+        *self.enemy_count = default();
+        *self.run_stats = default();
+        *self.weapon_pool = default();
+        *self.spawned_chunks = default();
+        *self.camera_shake = default();
+        *self.hit_stop = default();
+    }
+}
+
+fn teardown_game(
     mut commands: Commands,
-    mut game_timers: ResMut<GameTimers>,
+    mut run_resources: RunResources,
+    mut time: ResMut<Time<Virtual>>,
+    mut rng: ResMut<GameRng>,
     mut audio_instances: ResMut<Assets<AudioInstance>>,
     bgm: Res<Bgm>,
-    mut player_q: Query<(&player::PlayerInput, &mut Transform, &mut PlayerHealth, &mut weapons::Weapon)>,
-    enemy_q: Query<Entity, With<enemies::Enemy>>,
+    run_q: Query<Entity, With<RunEntity>>,
 ) {
-    // If reset_time is finished and player pressed reset input.
-    if !game_timers.reset_time.finished() {
-        return;
+    for entity in run_q.iter() {
+        commands.entity(entity).despawn_recursive();
     }
 
-    let (input, mut transform, mut health, mut weapon) = player_q.single_mut();
-    if !input.reset_game {
-        return;
+    run_resources.reset();
+    // Hit-stop only runs during a run, so it can't put the speed back itself.
+    if time.relative_speed() != 1.0 {
+        time.set_relative_speed(1.0);
     }
 
-    // Reset game timers.
-    game_timers.game_time.reset();
-    game_timers.game_time.unpause();
-    game_timers.reset_time.reset();
-    game_timers.reset_time.pause();
+    // The next run gets a new seed.
+    *rng = GameRng::new(rng::random_seed());
 
-    // Reset player.
-    transform.translation.x = 0.0;
-    transform.translation.y = 0.0;
-    health.current = health.max;
-    *weapon = weapons::Weapon::new(weapons::WeaponChoice::random());
+    if let Some(instance) = audio_instances.get_mut(&bgm.handle) {
+        instance.stop(AudioTween::default());
+    }
+}
 
-    // Kill all enemies.
-    for entity in enemy_q.iter() {
-        commands.entity(entity)
-            .insert(enemies::Death);
+fn pause_game(
+    mut time: ResMut<Time<Virtual>>,
+    mut audio_instances: ResMut<Assets<AudioInstance>>,
+    bgm: Res<Bgm>,
+) {
+    // Pausing virtual time also stops fixed timesteps, so game logic and physics stop too.
+    time.pause();
+
+    if let Some(instance) = audio_instances.get_mut(&bgm.handle) {
+        instance.pause(AudioTween::default());
     }
+}
+
+fn unpause_game(
+    mut time: ResMut<Time<Virtual>>,
+    mut audio_instances: ResMut<Assets<AudioInstance>>,
+    bgm: Res<Bgm>,
+) {
+    time.unpause();
 
     if let Some(instance) = audio_instances.get_mut(&bgm.handle) {
-        instance.seek_to(0.0);
+        instance.resume(AudioTween::default());
     }
 }
 
@@ -187,7 +323,7 @@ impl Lifetime {
     }
 }
 
-fn update_lifetimes(
+pub fn update_lifetimes(
     mut commands: Commands,
     time: Res<Time>,
     mut q: Query<(Entity, &mut Lifetime)>,
@@ -211,12 +347,14 @@ fn update_sprite_facing(
     }
 }
 
-fn camera_follows_player(
-    player_q: Query<&GlobalTransform, (With<player::Player>, Changed<Transform>)>,
+pub fn camera_follows_player(
+    player_q: Query<&Transform, With<player::Player>>,
     mut camera_q: Query<&mut Transform, (With<Camera>, Without<player::Player>)>,
 ) {
+    // Follow the player's interpolated transform, since its GlobalTransform hasn't been
+    // propagated yet. Runs every frame so camera shake never builds up.
     if let (Ok(mut camera_transform), Ok(player_transform)) = (camera_q.get_single_mut(), player_q.get_single()) {
-        camera_transform.translation.x = player_transform.translation().x;
-        camera_transform.translation.y = player_transform.translation().y;
+        camera_transform.translation.x = player_transform.translation.x;
+        camera_transform.translation.y = player_transform.translation.y;
     }
 }
@@ -0,0 +1,238 @@
+use bevy::prelude::*;
+use bevy::reflect::TypePath;
+use bevy::transform::TransformSystem;
+use serde::Deserialize;
+
+use crate::{
+    AppState, InRun,
+    assets::Configs,
+    combat::{self, HitEvent},
+    game::{self, GameLogicSet},
+    player::Player,
+    settings::Settings,
+    weapons::Explosion,
+    window::primary_window_exists,
+};
+
+/// Sprite colors multiply the texture, so an overbright color washes it out to white.
+const FLASH_COLOR: Color = Color::LinearRgba(LinearRgba::rgb(8.0, 8.0, 8.0));
+
+/// Hit flashes, hit-stop and screen shake, to make hits feel like they land.
+pub struct GameFeelPlugin;
+
+impl Plugin for GameFeelPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<CameraShake>()
+            .init_resource::<HitStop>()
+            .add_event::<ShakeEvent>()
+            .add_systems(FixedUpdate, (
+                react_to_hits.after(combat::check_hits),
+                shake_on_explosions,
+                tick_hit_flashes,
+            ).in_set(GameLogicSet))
+            // Hit-stop changes how fast time runs, so headless simulations leave it out to keep
+            // one update to one fixed step.
+            .add_systems(Update, apply_hit_stop
+                .run_if(in_state(InRun))
+                .run_if(primary_window_exists))
+            .add_systems(First, hide_hit_flashes)
+            .add_systems(PostUpdate, (
+                show_hit_flashes,
+                shake_camera
+                    .run_if(in_state(AppState::InGame))
+                    .after(game::camera_follows_player)
+                    .before(TransformSystem::TransformPropagate),
+            ));
+    }
+}
+
+#[derive(Deserialize, Asset, TypePath)]
+pub struct GameFeelConfig {
+    /// How long sprites flash white when hit.
+    pub hit_flash_secs: f32,
+    /// Hits dealing at least this much damage count as big hits.
+    pub big_hit_damage: f32,
+    /// How long time slows down for on big hits, in real seconds.
+    pub hit_stop_secs: f32,
+    /// How fast time runs during hit-stop. 1 is normal speed.
+    pub hit_stop_time_scale: f32,
+    /// How far the camera moves at full trauma, in pixels.
+    pub max_shake_offset: f32,
+    /// How much trauma wears off per second.
+    pub trauma_decay: f32,
+    /// Trauma added, from 0 to 1, by each kind of event.
+    pub big_hit_trauma: f32,
+    pub explosion_trauma: f32,
+    pub player_hit_trauma: f32,
+}
+
+/// Adds trauma, from 0 to 1, to the camera shake.
+#[derive(Event)]
+pub struct ShakeEvent(pub f32);
+
+/// The camera shakes more the more trauma it has, and trauma wears off over time.
+#[derive(Default, Resource)]
+pub struct CameraShake {
+    pub trauma: f32,
+}
+
+/// Real seconds left of slowed down time.
+#[derive(Default, Resource)]
+pub struct HitStop {
+    pub remaining: f32,
+}
+
+/// Makes a sprite flash white for a moment. Only shown while rendering, so game logic that changes
+/// sprite colors never sees the flash color.
+#[derive(Component)]
+pub struct HitFlash {
+    pub remaining: f32,
+    /// The sprite's real color while the flash color is showing.
+    hidden_color: Option<Color>,
+}
+
+fn react_to_hits(
+    mut commands: Commands,
+    configs: Configs,
+    mut hit_stop: ResMut<HitStop>,
+    mut hits: EventReader<HitEvent>,
+    mut shakes: EventWriter<ShakeEvent>,
+    mut flash_q: Query<Option<&mut HitFlash>, With<Sprite>>,
+    player_q: Query<(), With<Player>>,
+) {
+    let Some(config) = configs.feel() else {
+        return;
+    };
+
+    for hit in hits.read() {
+        // Status effects hit the player with themselves as the attacker.
+        if hit.damage <= 0.0 || hit.attacker == hit.defender {
+            continue;
+        }
+
+        match flash_q.get_mut(hit.defender) {
+            Ok(Some(mut flash)) => flash.remaining = config.hit_flash_secs,
+            Ok(None) => {
+                commands.entity(hit.defender).try_insert(HitFlash {
+                    remaining: config.hit_flash_secs,
+                    hidden_color: None,
+                });
+            }
+            Err(_) => {}
+        }
+
+        if player_q.contains(hit.defender) {
+            shakes.send(ShakeEvent(config.player_hit_trauma));
+        } else if hit.damage >= config.big_hit_damage {
+            shakes.send(ShakeEvent(config.big_hit_trauma));
+            hit_stop.remaining = hit_stop.remaining.max(config.hit_stop_secs);
+        }
+    }
+}
+
+fn shake_on_explosions(
+    configs: Configs,
+    mut shakes: EventWriter<ShakeEvent>,
+    explosion_q: Query<(), Added<Explosion>>,
+) {
+    let Some(config) = configs.feel() else {
+        return;
+    };
+
+    for _ in explosion_q.iter() {
+        shakes.send(ShakeEvent(config.explosion_trauma));
+    }
+}
+
+fn tick_hit_flashes(
+    time: Res<Time>,
+    mut flash_q: Query<&mut HitFlash>,
+) {
+    let dt = time.delta_seconds();
+    for mut flash in flash_q.iter_mut() {
+        flash.remaining -= dt;
+    }
+}
+
+/// Puts the real color back before anything else runs this frame, and removes finished flashes.
+fn hide_hit_flashes(
+    mut commands: Commands,
+    mut flash_q: Query<(Entity, &mut HitFlash, &mut Sprite)>,
+) {
+    for (entity, mut flash, mut sprite) in flash_q.iter_mut() {
+        if let Some(color) = flash.hidden_color.take() {
+            sprite.color = color;
+        }
+        if flash.remaining <= 0.0 {
+            commands.entity(entity).remove::<HitFlash>();
+        }
+    }
+}
+
+fn show_hit_flashes(
+    mut flash_q: Query<(&mut HitFlash, &mut Sprite)>,
+) {
+    for (mut flash, mut sprite) in flash_q.iter_mut() {
+        if flash.remaining <= 0.0 || flash.hidden_color.is_some() {
+            continue;
+        }
+
+        flash.hidden_color = Some(sprite.color);
+        let alpha = sprite.color.alpha();
+        sprite.color = FLASH_COLOR.with_alpha(alpha);
+    }
+}
+
+fn apply_hit_stop(
+    configs: Configs,
+    real_time: Res<Time<Real>>,
+    mut time: ResMut<Time<Virtual>>,
+    mut hit_stop: ResMut<HitStop>,
+) {
+    let Some(config) = configs.feel() else {
+        return;
+    };
+
+    if hit_stop.remaining > 0.0 {
+        hit_stop.remaining = (hit_stop.remaining - real_time.delta_seconds()).max(0.0);
+    }
+    let speed = if hit_stop.remaining > 0.0 { config.hit_stop_time_scale } else { 1.0 };
+    if time.relative_speed() != speed {
+        time.set_relative_speed(speed);
+    }
+}
+
+/// Runs after the camera has followed the player, so the shake is on top of where it should be.
+/// Only shakes while playing, so the pause and level up screens hold still.
+fn shake_camera(
+    configs: Configs,
+    settings: Res<Settings>,
+    time: Res<Time>,
+    mut shake: ResMut<CameraShake>,
+    mut shakes: EventReader<ShakeEvent>,
+    mut camera_q: Query<&mut Transform, With<Camera>>,
+) {
+    let Some(config) = configs.feel() else {
+        return;
+    };
+
+    for &ShakeEvent(trauma) in shakes.read() {
+        shake.trauma = (shake.trauma + trauma).min(1.0);
+    }
+    shake.trauma = (shake.trauma - config.trauma_decay * time.delta_seconds()).max(0.0);
+
+    if !settings.screen_shake || shake.trauma <= 0.0 {
+        return;
+    }
+    let Ok(mut camera_transform) = camera_q.get_single_mut() else {
+        return;
+    };
+
+    // Squaring trauma makes small shakes subtle and big ones violent. The offset is only for
+    // looks, so it doesn't use the run's random numbers.
+    let amount = shake.trauma * shake.trauma * config.max_shake_offset;
+    let offset = Vec2::new(fastrand::f32() * 2.0 - 1.0, fastrand::f32() * 2.0 - 1.0) * amount;
+    camera_transform.translation.x += offset.x;
+    camera_transform.translation.y += offset.y;
+}
@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy::time::TimeUpdateStrategy;
+use bevy_kira_audio::prelude::*;
+
+use crate::{
+    AppState, GAME_LOGIC_FPS, SimulationPlugin,
+    animation::Animation,
+    assets::{AudioAssets, AudioConfig, GameAssets},
+    enemies::{self, boss::BossesConfig, spawner::{Spawner, WaveSchedule}, EnemyKind},
+    game_feel::GameFeelConfig,
+    pickups::{self, DropTable, PickupKind},
+    player::{Player, PlayerInput},
+    rng::GameRng,
+    upgrades::{UpgradeChosen, UpgradesConfig},
+    weapons::WeaponsConfig,
+    window::WindowState,
+};
+
+/// Runs the game without a window, renderer, audio output or input devices, for automated
+/// gameplay tests.
+///
+/// Every update advances the game by exactly one fixed timestep. Nothing reads input devices, so
+/// the player only does what the test sets in their `PlayerInput`.
+pub struct Simulation {
+    pub app: App,
+}
+
+impl Simulation {
+    /// Starts a run with the given seed. The world is set up like in the game, so the player and
+    /// spawner already exist once this returns.
+    pub fn new(seed: u64) -> Self {
+        let mut app = App::new();
+        app
+            .add_plugins((
+                MinimalPlugins,
+                AssetPlugin::default(),
+                StatesPlugin,
+                TransformPlugin,
+                HierarchyPlugin,
+            ))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(timestep()))
+            // Skip loading and start the run right away.
+            .insert_state(AppState::MainMenu)
+            .add_plugins(SimulationPlugin)
+            .insert_resource(GameRng::new(seed));
+        add_stub_assets(&mut app);
+
+        let mut sim = Self {
+            app,
+        };
+        sim.set_state(AppState::InGame);
+        sim
+    }
+
+    /// Transitions to the given state and runs the update that sets it up.
+    pub fn set_state(&mut self, state: AppState) {
+        self.app.world_mut().resource_mut::<NextState<AppState>>().set(state);
+        self.step();
+    }
+
+    pub fn step(&mut self) {
+        self.app.update();
+    }
+
+    pub fn run_for(&mut self, secs: f32) {
+        let timesteps = (secs * GAME_LOGIC_FPS as f32).round() as u32;
+        for _ in 0..timesteps {
+            self.step();
+        }
+    }
+
+    pub fn elapsed_secs(&self) -> f32 {
+        self.app.world().resource::<Time<Fixed>>().elapsed_seconds()
+    }
+
+    pub fn player(&mut self) -> Entity {
+        self.app.world_mut()
+            .query_filtered::<Entity, With<Player>>()
+            .single(self.app.world())
+    }
+
+    /// What the player does from now on, until it's set again.
+    pub fn set_input(&mut self, input: PlayerInput) {
+        let player = self.player();
+        *self.app.world_mut().get_mut::<PlayerInput>(player).unwrap() = input;
+    }
+
+    pub fn spawn_enemy(&mut self, kind: EnemyKind, pos: Vec2) -> Entity {
+        let world = self.app.world_mut();
+        let enemy = world.resource_scope(|world, assets: Mut<GameAssets>| {
+            let mut commands = world.commands();
+            enemies::spawn_enemy(kind, pos, &mut commands, &assets)
+        });
+        world.flush();
+        enemy
+    }
+
+    pub fn spawn_pickup(&mut self, kind: PickupKind, pos: Vec2) -> Entity {
+        let world = self.app.world_mut();
+        let pickup = world.resource_scope(|world, assets: Mut<GameAssets>| {
+            let mut commands = world.commands();
+            pickups::spawn_pickup(kind, pos, &mut commands, &assets)
+        });
+        world.flush();
+        pickup
+    }
+
+    /// Picks one of the upgrades on the level up screen, by its position in the list, and runs
+    /// the update that applies it.
+    pub fn choose_upgrade(&mut self, option: usize) {
+        self.app.world_mut().send_event(UpgradeChosen(option));
+        self.step();
+    }
+
+    /// Stops the spawner, so only enemies the test spawns are around.
+    pub fn pause_spawner(&mut self) {
+        let world = self.app.world_mut();
+        let mut spawner = world.query::<&mut Spawner>().single_mut(world);
+        if !spawner.cooldown.paused() {
+            spawner.toggle();
+        }
+    }
+
+    pub fn get<T: Component>(&self, entity: Entity) -> Option<&T> {
+        self.app.world().get::<T>(entity)
+    }
+}
+
+fn timestep() -> Duration {
+    Duration::from_secs_f64(1.0 / GAME_LOGIC_FPS as f64)
+}
+
+/// Stands in for everything the loading state and windowed plugins would have set up. Images,
+/// animations and sounds are left as default handles, since nothing ever draws or plays them.
+fn add_stub_assets(app: &mut App) {
+    app
+        .init_asset::<Animation>()
+        .init_asset::<AudioConfig>()
+        .init_asset::<AudioInstance>()
+        .init_asset::<AudioSource>()
+        .init_asset::<BossesConfig>()
+        .init_asset::<DropTable>()
+        .init_asset::<GameFeelConfig>()
+        .init_asset::<UpgradesConfig>()
+        .init_asset::<WaveSchedule>()
+        .init_asset::<WeaponsConfig>()
+        .init_resource::<Audio>()
+        .init_resource::<WindowState>();
+
+    // Use the real weapons, bosses, waves, drops, upgrades and game feel, so tests catch balance
+    // changes.
+    let weapons: WeaponsConfig = ron::from_str(include_str!("../assets/config.weapons.ron"))
+        .expect("Could not deserialize weapons config");
+    let weapons = app.world_mut().resource_mut::<Assets<WeaponsConfig>>().add(weapons);
+    let bosses: BossesConfig = ron::from_str(include_str!("../assets/config.bosses.ron"))
+        .expect("Could not deserialize bosses config");
+    let bosses = app.world_mut().resource_mut::<Assets<BossesConfig>>().add(bosses);
+    let waves: WaveSchedule = ron::from_str(include_str!("../assets/config.waves.ron"))
+        .expect("Could not deserialize wave schedule");
+    let waves = app.world_mut().resource_mut::<Assets<WaveSchedule>>().add(waves);
+    let drops: DropTable = ron::from_str(include_str!("../assets/config.drops.ron"))
+        .expect("Could not deserialize drop table");
+    let drops = app.world_mut().resource_mut::<Assets<DropTable>>().add(drops);
+    let upgrades: UpgradesConfig = ron::from_str(include_str!("../assets/config.upgrades.ron"))
+        .expect("Could not deserialize upgrades config");
+    let upgrades = app.world_mut().resource_mut::<Assets<UpgradesConfig>>().add(upgrades);
+    let feel: GameFeelConfig = ron::from_str(include_str!("../assets/config.feel.ron"))
+        .expect("Could not deserialize game feel config");
+    let feel = app.world_mut().resource_mut::<Assets<GameFeelConfig>>().add(feel);
+    app.insert_resource(GameAssets {
+        weapons,
+        bosses,
+        waves,
+        drops,
+        upgrades,
+        feel,
+        ..default()
+    });
+
+    let config = app.world_mut().resource_mut::<Assets<AudioConfig>>().add(AudioConfig::default());
+    app.insert_resource(AudioAssets {
+        config,
+        ..default()
+    });
+}
@@ -1,5 +1,17 @@
 use bevy::prelude::*;
 
+/// Player health is counted in half hearts, so this is one whole heart.
+pub const HEART: u8 = 2;
+/// Max health can't be raised past this, so the heart row still fits on screen.
+pub const MAX_PLAYER_HEALTH: u8 = 12 * HEART;
+
+/// Converts an amount of hearts, like damage from a hit, to half hearts. Partial half hearts
+/// round up.
+pub fn half_hearts(hearts: f32) -> u8 {
+    (hearts * HEART as f32).ceil() as u8
+}
+
+/// Counted in half hearts.
 #[derive(Default, Component, Reflect)]
 #[reflect(Component)]
 pub struct PlayerHealth {
@@ -31,6 +43,22 @@ impl PlayerHealth {
         self.current -= lost;
         lost
     }
+
+    /// Returns how much health was actually gained.
+    pub fn heal(&mut self, amount: u8) -> u8 {
+        let gained = amount.min(self.missing());
+        self.current += gained;
+        gained
+    }
+
+    /// Raises max health, up to `MAX_PLAYER_HEALTH`, and fills in the new health. Returns how much
+    /// max health was actually gained.
+    pub fn raise_max(&mut self, amount: u8) -> u8 {
+        let gained = amount.min(MAX_PLAYER_HEALTH.saturating_sub(self.max));
+        self.max += gained;
+        self.heal(gained);
+        gained
+    }
 }
 
 #[derive(Default, Component, Reflect)]
@@ -0,0 +1,165 @@
+use bevy::prelude::*;
+use bevy::utils::SystemTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AppState,
+    assets::GameAssets,
+    game::GameTimers,
+    rng::GameRng,
+    stats::RunStats,
+    weapons::WeaponsConfig,
+};
+
+/// Saved next to the window state on native, or under this key in local storage on web.
+const HIGH_SCORES_FILENAME: &str = "high_scores.ron";
+const MAX_HIGH_SCORES: usize = 10;
+
+pub struct HighScoresPlugin;
+
+impl Plugin for HighScoresPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(load_high_scores())
+            .add_systems(OnEnter(AppState::GameOver), record_high_score);
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HighScore {
+    /// Survival time in seconds.
+    pub time: f32,
+    pub kills: u32,
+    pub seed: u64,
+    /// Seconds since the Unix epoch.
+    pub date: u64,
+    /// Name of the weapon that dealt the most damage.
+    pub top_weapon: Option<String>,
+}
+
+impl HighScore {
+    /// The date as YYYY-MM-DD, in UTC.
+    pub fn date_string(&self) -> String {
+        // Convert days since the epoch to a civil date, from:
+        // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let days = (self.date / 86400) as i64 + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days.rem_euclid(146097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+#[derive(Default, Debug, Deserialize, Serialize, Resource)]
+pub struct HighScores {
+    /// Best runs first.
+    pub scores: Vec<HighScore>,
+    /// Where the last run placed in the table, if it made it.
+    #[serde(skip)]
+    pub last_rank: Option<usize>,
+}
+
+impl HighScores {
+    /// Returns where the score placed in the table, if it made it.
+    pub fn add(&mut self, score: HighScore) -> Option<usize> {
+        let rank = self.scores.iter()
+            .position(|other| score.time > other.time)
+            .unwrap_or(self.scores.len());
+        if rank >= MAX_HIGH_SCORES {
+            return None;
+        }
+
+        self.scores.insert(rank, score);
+        self.scores.truncate(MAX_HIGH_SCORES);
+        Some(rank)
+    }
+}
+
+fn record_high_score(
+    mut high_scores: ResMut<HighScores>,
+    game_timers: Res<GameTimers>,
+    run_stats: Res<RunStats>,
+    rng: Res<GameRng>,
+    assets: Res<GameAssets>,
+    weapons_configs: Res<Assets<WeaponsConfig>>,
+) {
+    let top_weapon = run_stats.damage_dealt.iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .and_then(|(choice, _)| {
+            let weapons = weapons_configs.get(&assets.weapons)?;
+            Some(weapons.get(*choice).name.clone())
+        });
+    let date = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or_default();
+
+    let score = HighScore {
+        time: game_timers.game_time.elapsed_secs(),
+        kills: run_stats.total_kills(),
+        seed: rng.seed(),
+        date,
+        top_weapon,
+    };
+    high_scores.last_rank = high_scores.add(score);
+
+    // Save right away, since browsers don't let us save on exit.
+    if high_scores.last_rank.is_some() {
+        save_high_scores(&high_scores);
+    }
+}
+
+fn load_high_scores() -> HighScores {
+    let Some(high_scores_str) = read_high_scores() else {
+        return default();
+    };
+    ron::from_str(&high_scores_str).unwrap_or_else(|e| {
+        warn!("Could not deserialize high scores, starting a new table: {}", e);
+        default()
+    })
+}
+
+fn save_high_scores(high_scores: &HighScores) {
+    info!("Saving high scores");
+
+    let pretty_config = ron::ser::PrettyConfig::default();
+    let high_scores_str = ron::ser::to_string_pretty(high_scores, pretty_config)
+        .expect("Could not serialize high scores");
+    write_high_scores(&high_scores_str);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_high_scores() -> Option<String> {
+    std::fs::read_to_string(HIGH_SCORES_FILENAME).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_high_scores(high_scores_str: &str) {
+    if let Err(e) = std::fs::write(HIGH_SCORES_FILENAME, high_scores_str) {
+        warn!("Could not write high scores to file: {}", e);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_high_scores() -> Option<String> {
+    local_storage()?.get_item(HIGH_SCORES_FILENAME).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_high_scores(high_scores_str: &str) {
+    let saved = local_storage()
+        .is_some_and(|storage| storage.set_item(HIGH_SCORES_FILENAME, high_scores_str).is_ok());
+    if !saved {
+        warn!("Could not write high scores to local storage");
+    }
+}
@@ -0,0 +1,85 @@
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+
+/// Smooths out rendering of entities moved by game logic in `FixedUpdate`.
+///
+/// Game logic and physics only move entities once per fixed timestep, so rendering those
+/// positions directly looks choppy whenever the frame rate doesn't match `GAME_LOGIC_FPS`.
+/// Instead, we render entities somewhere between their last two fixed timestep positions and put
+/// them back before the next fixed timestep runs.
+pub struct InterpolationPlugin;
+
+impl Plugin for InterpolationPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(FixedFirst, restore_fixed_transforms)
+            .add_systems(FixedLast, record_fixed_transforms)
+            .add_systems(PostUpdate, interpolate_transforms
+                .in_set(InterpolationSet)
+                .before(TransformSystem::TransformPropagate));
+    }
+}
+
+/// Runs in `PostUpdate` once entities have been moved to their interpolated positions.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InterpolationSet;
+
+/// Only add this to entities without a parent, since it restores their `GlobalTransform` directly.
+#[derive(Default, Component)]
+pub struct Interpolated {
+    /// Translation at the end of the second to last fixed timestep.
+    previous: Option<Vec3>,
+    /// Translation at the end of the last fixed timestep.
+    current: Option<Vec3>,
+    /// Translation we last rendered this entity at, so we can tell if something else moved it.
+    rendered: Option<Vec3>,
+}
+
+impl Interpolated {
+    /// If something other than game logic moved the entity since we rendered it (e.g. it was
+    /// teleported), treat its new position as the real one instead of interpolating to it.
+    fn sync_external_move(&mut self, translation: Vec3) {
+        if self.rendered.is_some_and(|rendered| rendered != translation) {
+            self.previous = Some(translation);
+            self.current = Some(translation);
+        }
+    }
+}
+
+fn restore_fixed_transforms(
+    mut q: Query<(&mut Interpolated, &mut Transform, &mut GlobalTransform)>,
+) {
+    for (mut interpolated, mut transform, mut global_transform) in q.iter_mut() {
+        interpolated.sync_external_move(transform.translation);
+        if let Some(current) = interpolated.current {
+            transform.translation = current;
+        }
+        interpolated.rendered = None;
+
+        // Game logic may read GlobalTransform before physics propagates transforms again.
+        *global_transform = GlobalTransform::from(*transform);
+    }
+}
+
+fn record_fixed_transforms(
+    mut q: Query<(&mut Interpolated, &Transform)>,
+) {
+    for (mut interpolated, transform) in q.iter_mut() {
+        interpolated.previous = interpolated.current.or(Some(transform.translation));
+        interpolated.current = Some(transform.translation);
+    }
+}
+
+fn interpolate_transforms(
+    fixed_time: Res<Time<Fixed>>,
+    mut q: Query<(&mut Interpolated, &mut Transform)>,
+) {
+    let alpha = fixed_time.overstep_fraction();
+    for (mut interpolated, mut transform) in q.iter_mut() {
+        interpolated.sync_external_move(transform.translation);
+        if let (Some(previous), Some(current)) = (interpolated.previous, interpolated.current) {
+            transform.translation = previous.lerp(current, alpha);
+            interpolated.rendered = Some(transform.translation);
+        }
+    }
+}
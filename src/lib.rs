@@ -0,0 +1,166 @@
+use bevy::prelude::*;
+use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
+use bevy::window::{Cursor, WindowMode};
+use bevy_kira_audio::AudioPlugin;
+use bevy_rapier2d::prelude::*;
+
+mod animation;
+pub mod assets;
+pub mod combat;
+pub mod damage_numbers;
+mod debug;
+pub mod enemies;
+pub mod game;
+pub mod game_feel;
+pub mod headless;
+pub mod health;
+mod high_scores;
+mod interpolation;
+mod log;
+mod menus;
+mod physics;
+pub mod pickups;
+pub mod player;
+mod replay;
+pub mod rng;
+pub mod settings;
+pub mod stats;
+pub mod status;
+mod terrain;
+mod ui;
+pub mod upgrades;
+pub mod weapons;
+mod window;
+
+// TODO: Choose a good size for this game.
+// const GAME_SIZE: (f32, f32) = (320.0, 180.0);
+const DEFAULT_SCALE: u8 = 3;
+pub const GAME_LOGIC_FPS: u8 = 60;
+pub const GAME_LOGIC_FRAME_TIME: f32 = 1.0 / GAME_LOGIC_FPS as f32;
+const ALLOW_EXIT: bool = cfg!(not(target_arch = "wasm32"));
+
+/// Get the value passed after `name` on the command line, e.g. `--seed 1234`.
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args();
+    args.find(|arg| arg == name)?;
+    args.next()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, States)]
+pub enum AppState {
+    #[default]
+    Loading,
+    MainMenu,
+    InGame,
+    Paused,
+    /// Picking an upgrade. Gameplay is paused in the meantime.
+    LevelUp,
+    GameOver,
+}
+
+/// Exists while a run is in progress, including while it's paused, levelling up or showing the
+/// game over screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InRun;
+
+impl ComputedStates for InRun {
+    type SourceStates = AppState;
+
+    fn compute(app_state: AppState) -> Option<Self> {
+        match app_state {
+            AppState::InGame | AppState::Paused | AppState::LevelUp | AppState::GameOver => Some(InRun),
+            AppState::Loading | AppState::MainMenu => None,
+        }
+    }
+}
+
+/// Game logic and physics running at a fixed timestep. Doesn't need a window, so headless
+/// simulations use it too.
+struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            // Run game logic and physics at a fixed rate, independent of frame rate.
+            .insert_resource(Time::<Fixed>::from_hz(GAME_LOGIC_FPS as f64))
+            .insert_resource(RapierConfiguration {
+                timestep_mode: TimestepMode::Fixed {
+                    dt: GAME_LOGIC_FRAME_TIME,
+                    substeps: 1,
+                },
+                ..RapierConfiguration::new(1.0)
+            })
+            .add_plugins(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(1.0).in_fixed_schedule())
+            .init_state::<AppState>()
+            .add_computed_state::<InRun>()
+            .add_plugins((
+                interpolation::InterpolationPlugin,
+                game::GamePlugin,
+            ));
+    }
+}
+
+pub fn run() {
+    // When building for WASM, print panics to the browser console.
+    #[cfg(target_arch = "wasm32")]
+    console_error_panic_hook::set_once();
+
+    // TODO: Try to initialize logging before this. Maybe we can also make this code run in a plugin.
+    let saved_window_state = window::load_window_state();
+    let cursor = Cursor {
+        visible: false,
+        ..default()
+    };
+
+    // Configure DefaultPlugins.
+    let default_plugins = DefaultPlugins
+        .set(log::log_plugin())
+        .set(ImagePlugin::default_nearest())
+        .set(WindowPlugin {
+            primary_window: Some(Window {
+                title: window::WINDOW_TITLE.into(),
+                // width: GAME_SIZE.0 * saved_window_state.scale as f32,
+                // height: GAME_SIZE.1 * saved_window_state.scale as f32,
+                resizable: false,
+                position: saved_window_state.position,
+                mode: WindowMode::Windowed,
+                cursor,
+                ..default()
+            }),
+            ..default()
+        });
+
+    let mut app = App::new();
+    app
+        .insert_resource(ClearColor(Color::srgb_u8(160, 160, 160)))
+
+        // External plugins
+        .add_plugins(default_plugins)
+        .add_plugins(FrameTimeDiagnosticsPlugin)
+        .add_plugins(bevy_egui::EguiPlugin)
+        .insert_resource(bevy_egui::EguiSettings {
+            // NOTE: Scaling down egui to make in-game UI look chunkier.
+            // TODO: Take DPI scaling into account as well.
+            scale_factor: (saved_window_state.scale as f32) / 2.0,
+            ..default()
+        })
+        .add_plugins(AudioPlugin)
+
+        // App setup
+        .add_plugins((
+            window::WindowPlugin::new(saved_window_state),
+            animation::AnimationPlugin,
+            SimulationPlugin,
+            assets::AssetsPlugin,
+            ui::UiPlugin,
+            menus::MenusPlugin,
+            high_scores::HighScoresPlugin,
+            // Added after SimulationPlugin so the saved settings replace the defaults.
+            settings::SettingsPlugin,
+            debug::DebugPlugin,
+            // Added after SimulationPlugin so a replay can override the run seed.
+            replay::ReplayPlugin,
+        ));
+
+    app.run();
+}
@@ -0,0 +1,342 @@
+use bevy::prelude::*;
+use bevy::app::AppExit;
+use bevy::ecs::system::SystemParam;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{
+    AppState,
+    assets::Configs,
+    game::GameTimers,
+    high_scores::HighScores,
+    player::Player,
+    replay::not_playing_back,
+    settings::{self, Settings},
+    stats::RunStats,
+    upgrades::{LevelUpChoices, UpgradeChosen, Upgrades},
+    weapons::WeaponsConfig,
+    window::primary_window_exists,
+};
+
+pub struct MenusPlugin;
+
+impl Plugin for MenusPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, (
+                main_menu.run_if(in_state(AppState::MainMenu)),
+                pause_on_input.run_if(in_state(AppState::InGame)),
+                pause_menu.run_if(in_state(AppState::Paused)),
+                // Replays pick the same upgrades that were picked in the run.
+                level_up_menu.run_if(in_state(AppState::LevelUp)).run_if(not_playing_back),
+                game_over_menu.run_if(in_state(AppState::GameOver)),
+            ).distributive_run_if(primary_window_exists));
+    }
+}
+
+const TITLE_SIZE: f32 = 60.0;
+const TEXT_SIZE: f32 = 30.0;
+const SMALL_TEXT_SIZE: f32 = 20.0;
+
+/// Keyboard and gamepad buttons for getting around the menus.
+#[derive(SystemParam)]
+struct MenuInput<'w> {
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    gamepads: Res<'w, Gamepads>,
+    pad_buttons: Res<'w, ButtonInput<GamepadButton>>,
+}
+
+impl MenuInput<'_> {
+    fn pad_just_pressed(&self, button: GamepadButtonType) -> bool {
+        self.gamepads.iter().any(|gamepad| {
+            self.pad_buttons.just_pressed(GamepadButton::new(gamepad, button))
+        })
+    }
+
+    fn confirm_pressed(&self) -> bool {
+        self.pad_just_pressed(GamepadButtonType::Start)
+            || self.pad_just_pressed(GamepadButtonType::South)
+            || self.keys.any_just_pressed([KeyCode::Space, KeyCode::Enter])
+    }
+
+    fn pause_pressed(&self) -> bool {
+        self.pad_just_pressed(GamepadButtonType::Start) || self.keys.just_pressed(KeyCode::Escape)
+    }
+}
+
+fn menu_window(title: &str) -> egui::Window<'_> {
+    egui::Window::new(title)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, -80.0])
+        .auto_sized()
+        .title_bar(false)
+        .frame(egui::Frame::none())
+}
+
+fn menu_text(text: impl Into<String>, size: f32) -> egui::RichText {
+    egui::RichText::new(text)
+        .color(egui::Color32::WHITE)
+        .background_color(egui::Color32::from_rgba_unmultiplied(0, 0, 0, 40))
+        .size(size)
+}
+
+fn draw_high_scores(
+    ui: &mut egui::Ui,
+    high_scores: &HighScores,
+    highlight: Option<usize>,
+) {
+    ui.label(menu_text("HIGH SCORES", TEXT_SIZE));
+    if high_scores.scores.is_empty() {
+        ui.label(menu_text("No runs yet!", SMALL_TEXT_SIZE));
+        return;
+    }
+
+    egui::Grid::new("HighScores")
+        .spacing([30.0, 4.0])
+        .show(ui, |ui| {
+            for header in ["", "Time", "Kills", "Best Weapon", "Date", "Seed"] {
+                ui.label(menu_text(header, SMALL_TEXT_SIZE));
+            }
+            ui.end_row();
+
+            for (i, score) in high_scores.scores.iter().enumerate() {
+                let color = if Some(i) == highlight {
+                    egui::Color32::YELLOW
+                } else {
+                    egui::Color32::WHITE
+                };
+                let columns = [
+                    format!("{}.", i + 1),
+                    format!("{:.0}s", score.time),
+                    score.kills.to_string(),
+                    score.top_weapon.clone().unwrap_or_else(|| "-".into()),
+                    score.date_string(),
+                    score.seed.to_string(),
+                ];
+                for column in columns {
+                    ui.label(menu_text(column, SMALL_TEXT_SIZE).color(color));
+                }
+                ui.end_row();
+            }
+        });
+}
+
+/// Saves the settings as soon as one is changed.
+fn draw_settings(ui: &mut egui::Ui, settings: &mut Settings) {
+    let mut changed = ui.checkbox(&mut settings.damage_numbers, menu_text("Damage numbers", SMALL_TEXT_SIZE)).changed();
+    changed |= ui.checkbox(&mut settings.screen_shake, menu_text("Screen shake", SMALL_TEXT_SIZE)).changed();
+    if changed {
+        settings::save_settings(settings);
+    }
+}
+
+fn main_menu(
+    mut egui_ctx: EguiContexts,
+    mut settings: ResMut<Settings>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut exit: EventWriter<AppExit>,
+    high_scores: Res<HighScores>,
+    input: MenuInput,
+) {
+    let mut play = input.confirm_pressed();
+    let mut quit = false;
+
+    menu_window("MainMenu").show(egui_ctx.ctx_mut(), |ui| {
+        ui.vertical_centered_justified(|ui| {
+            ui.label(menu_text("RE-ROLLING!", TITLE_SIZE));
+            ui.add_space(20.0);
+            play |= ui.button(menu_text("Play", TEXT_SIZE)).clicked();
+            if crate::ALLOW_EXIT {
+                quit |= ui.button(menu_text("Quit", TEXT_SIZE)).clicked();
+            }
+            ui.add_space(20.0);
+            ui.label(menu_text("Press Space on keyboard or Start on gamepad to Play", TEXT_SIZE));
+            ui.add_space(20.0);
+            draw_settings(ui, &mut settings);
+            ui.add_space(20.0);
+            draw_high_scores(ui, &high_scores, None);
+        });
+    });
+
+    if play {
+        next_state.set(AppState::InGame);
+    } else if quit {
+        exit.send(AppExit::Success);
+    }
+}
+
+fn pause_on_input(
+    mut next_state: ResMut<NextState<AppState>>,
+    input: MenuInput,
+) {
+    if input.pause_pressed() {
+        next_state.set(AppState::Paused);
+    }
+}
+
+fn pause_menu(
+    mut egui_ctx: EguiContexts,
+    mut settings: ResMut<Settings>,
+    mut next_state: ResMut<NextState<AppState>>,
+    input: MenuInput,
+) {
+    let mut resume = input.pause_pressed();
+    let mut quit = false;
+
+    menu_window("PauseMenu").show(egui_ctx.ctx_mut(), |ui| {
+        ui.vertical_centered_justified(|ui| {
+            ui.label(menu_text("PAUSED", TITLE_SIZE));
+            ui.add_space(20.0);
+            resume |= ui.button(menu_text("Resume", TEXT_SIZE)).clicked();
+            quit |= ui.button(menu_text("Main Menu", TEXT_SIZE)).clicked();
+            ui.add_space(20.0);
+            draw_settings(ui, &mut settings);
+        });
+    });
+
+    if resume {
+        next_state.set(AppState::InGame);
+    } else if quit {
+        next_state.set(AppState::MainMenu);
+    }
+}
+
+fn level_up_menu(
+    mut egui_ctx: EguiContexts,
+    mut chosen: EventWriter<UpgradeChosen>,
+    configs: Configs,
+    choices: Option<Res<LevelUpChoices>>,
+    input: MenuInput,
+    upgrades_q: Query<&Upgrades, With<Player>>,
+) {
+    let (Some(config), Some(choices)) = (configs.upgrades(), choices) else {
+        return;
+    };
+    let Ok(upgrades) = upgrades_q.get_single() else {
+        return;
+    };
+
+    // Number keys or the face buttons from left to right pick an option.
+    let keys_and_buttons = [
+        (KeyCode::Digit1, GamepadButtonType::West),
+        (KeyCode::Digit2, GamepadButtonType::North),
+        (KeyCode::Digit3, GamepadButtonType::East),
+    ];
+    let mut picked = keys_and_buttons.iter()
+        .take(choices.options.len())
+        .position(|&(key, button)| {
+            input.keys.just_pressed(key) || input.pad_just_pressed(button)
+        });
+
+    menu_window("LevelUpMenu").show(egui_ctx.ctx_mut(), |ui| {
+        ui.vertical_centered_justified(|ui| {
+            ui.label(menu_text("LEVEL UP!", TITLE_SIZE));
+            ui.add_space(20.0);
+            for (i, &index) in choices.options.iter().enumerate() {
+                let Some(def) = config.upgrades.get(index) else {
+                    continue;
+                };
+                let text = format!(
+                    "{}. {}: {} ({}/{})",
+                    i + 1,
+                    def.name,
+                    def.effect.description(),
+                    upgrades.stacks(index),
+                    def.max_stacks,
+                );
+                if ui.button(menu_text(text, TEXT_SIZE)).clicked() {
+                    picked = Some(i);
+                }
+            }
+            ui.add_space(20.0);
+            ui.label(menu_text("Press 1, 2 or 3 on keyboard or X, Y or B on gamepad to pick", SMALL_TEXT_SIZE));
+        });
+    });
+
+    if let Some(option) = picked {
+        chosen.send(UpgradeChosen(option));
+    }
+}
+
+fn draw_run_stats(
+    ui: &mut egui::Ui,
+    stats: &RunStats,
+    weapons: Option<&WeaponsConfig>,
+) {
+    let mut kills: Vec<_> = stats.kills.iter().collect();
+    kills.sort_by_key(|(kind, _)| kind.name());
+    let mut damage_dealt: Vec<_> = stats.damage_dealt.iter().collect();
+    damage_dealt.sort_by_key(|(choice, _)| choice.0);
+
+    let row = |ui: &mut egui::Ui, name: &str, value: String| {
+        ui.label(menu_text(name, SMALL_TEXT_SIZE));
+        ui.label(menu_text(value, SMALL_TEXT_SIZE));
+        ui.end_row();
+    };
+
+    ui.label(menu_text("THIS RUN", TEXT_SIZE));
+    egui::Grid::new("RunStats")
+        .spacing([40.0, 4.0])
+        .show(ui, |ui| {
+            row(ui, "Kills", stats.total_kills().to_string());
+            row(ui, "Bosses", stats.bosses_killed.to_string());
+            for (kind, count) in kills {
+                row(ui, &format!("    {}", kind.name()), count.to_string());
+            }
+            row(ui, "Damage Dealt", "".into());
+            for (choice, damage) in damage_dealt {
+                let name = weapons.map(|weapons| weapons.get(*choice).name.as_str()).unwrap_or("???");
+                row(ui, &format!("    {}", name), format!("{:.0}", damage));
+            }
+            row(ui, "Shots Fired", stats.shots_fired.to_string());
+            row(ui, "Accuracy", format!("{:.0}%", stats.accuracy() * 100.0));
+            row(ui, "Re-Rolls", stats.rerolls.to_string());
+            row(ui, "Damage Taken", format!("{} hearts", stats.damage_taken));
+            row(ui, "Most Enemies", stats.peak_enemy_count.to_string());
+        });
+}
+
+fn game_over_menu(
+    mut egui_ctx: EguiContexts,
+    mut next_state: ResMut<NextState<AppState>>,
+    configs: Configs,
+    run_stats: Res<RunStats>,
+    high_scores: Res<HighScores>,
+    game_timers: Res<GameTimers>,
+    input: MenuInput,
+) {
+    // Give the player a moment to see what killed them.
+    if !game_timers.reset_time.finished() {
+        return;
+    }
+
+    let mut try_again = input.confirm_pressed();
+    let mut quit = false;
+
+    menu_window("GameOverMenu").show(egui_ctx.ctx_mut(), |ui| {
+        ui.vertical_centered_justified(|ui| {
+            ui.label(menu_text("YOU ROLLED... POORLY", TITLE_SIZE));
+            let survived = format!("You survived for {:.0} seconds", game_timers.game_time.elapsed_secs());
+            ui.label(menu_text(survived, TEXT_SIZE));
+            if let Some(rank) = high_scores.last_rank {
+                ui.label(menu_text(format!("New high score! #{}", rank + 1), TEXT_SIZE).color(egui::Color32::YELLOW));
+            }
+            ui.add_space(20.0);
+            try_again |= ui.button(menu_text("Try Again", TEXT_SIZE)).clicked();
+            quit |= ui.button(menu_text("Main Menu", TEXT_SIZE)).clicked();
+            ui.add_space(20.0);
+            ui.label(menu_text("Press Space on keyboard or Start on gamepad to Try Again", TEXT_SIZE));
+            ui.add_space(20.0);
+            // Side by side so it all fits on screen.
+            ui.horizontal_top(|ui| {
+                ui.vertical(|ui| draw_run_stats(ui, &run_stats, configs.weapons()));
+                ui.add_space(40.0);
+                ui.vertical(|ui| draw_high_scores(ui, &high_scores, high_scores.last_rank));
+            });
+        });
+    });
+
+    if try_again {
+        next_state.set(AppState::InGame);
+    } else if quit {
+        next_state.set(AppState::MainMenu);
+    }
+}
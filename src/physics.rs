@@ -9,6 +9,7 @@ pub mod groups {
     pub const HURT : Group = Group::GROUP_3;
     pub const PLAYER : Group = Group::GROUP_4;
     pub const ENEMY : Group = Group::GROUP_5;
+    pub const PICKUP : Group = Group::GROUP_6;
 }
 
 #[derive(Bundle)]
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy::math::Mat2;
+use bevy::reflect::TypePath;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    assets::GameAssets,
+    enemies::{self, Death, EnemyKind},
+    game::{GameLogicSet, Lifetime, RunEntity},
+    health::{self, PlayerHealth, HEART},
+    physics::groups,
+    player::Player,
+    rng::GameRng,
+    upgrades::Experience,
+};
+
+/// How long a pickup sticks around before disappearing.
+const PICKUP_LIFETIME: f32 = 15.0;
+/// Pickups blink for this long before disappearing.
+const PICKUP_BLINK_SECS: f32 = 3.0;
+const PICKUP_BLINKS_PER_SEC: f32 = 8.0;
+const PICKUP_Z: f32 = 5.0;
+const PICKUP_RADIUS: f32 = 5.0;
+/// Pickups this close to the player fly towards them.
+const MAGNET_RADIUS: f32 = 40.0;
+const MAGNET_SPEED: f32 = 150.0;
+/// Drawn bigger than hearts, so they stand out.
+const HEART_CONTAINER_SIZE: f32 = 24.0;
+const XP_COLOR: Color = Color::srgb(0.3, 0.9, 1.0);
+/// How far apart drops from the same enemy land.
+const DROP_SCATTER: f32 = 8.0;
+
+pub struct PickupsPlugin;
+
+impl Plugin for PickupsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(FixedUpdate, (
+                drop_pickups
+                    .after(enemies::trigger_enemy_death)
+                    .before(enemies::despawn_dead_enemies),
+                magnetize_pickups,
+                collect_pickups,
+                blink_expiring_pickups,
+            ).in_set(GameLogicSet));
+    }
+}
+
+/// What each kind of enemy can drop when it dies.
+#[derive(Deserialize, Asset, TypePath)]
+pub struct DropTable {
+    pub drops: HashMap<EnemyKind, Vec<DropChance>>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct DropChance {
+    pub pickup: PickupKind,
+    /// From 0 to 1. Each drop rolls separately, so an enemy can drop several things.
+    pub chance: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum PickupKind {
+    /// Restores this many hearts.
+    Heart(f32),
+    /// Adds a heart to max health.
+    HeartContainer,
+    /// Gives this much experience.
+    Xp(u32),
+}
+
+#[derive(Component)]
+pub struct Pickup {
+    pub kind: PickupKind,
+    /// Once the player gets close, the pickup keeps following them until collected.
+    magnetized: bool,
+}
+
+#[derive(Bundle)]
+pub struct PickupBundle {
+    pickup: Pickup,
+    name: Name,
+    sprite: SpriteBundle,
+
+    body: RigidBody,
+    collider: Collider,
+    layers: CollisionGroups,
+    sensor: Sensor,
+    active_events: ActiveEvents,
+    lifetime: Lifetime,
+    run_entity: RunEntity,
+}
+
+impl PickupBundle {
+    pub fn new(kind: PickupKind, pos: Vec2, assets: &GameAssets) -> Self {
+        let (name, texture, size, color) = match kind {
+            PickupKind::Heart(_) => ("HeartPickup", assets.heart_drop.clone(), None, Color::WHITE),
+            PickupKind::HeartContainer => {
+                ("HeartContainerPickup", assets.whole_heart.clone(), Some(Vec2::splat(HEART_CONTAINER_SIZE)), Color::WHITE)
+            }
+            PickupKind::Xp(_) => ("XpPickup", assets.projectiles.clone(), None, XP_COLOR),
+        };
+
+        Self {
+            pickup: Pickup {
+                kind,
+                magnetized: false,
+            },
+            name: Name::new(name),
+            sprite: SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: size,
+                    ..default()
+                },
+                texture,
+                transform: Transform::from_translation(pos.extend(PICKUP_Z)),
+                ..default()
+            },
+            body: RigidBody::KinematicPositionBased,
+            collider: Collider::ball(PICKUP_RADIUS),
+            layers: CollisionGroups::new(groups::PICKUP, groups::PLAYER),
+            sensor: Sensor,
+            active_events: ActiveEvents::COLLISION_EVENTS,
+            lifetime: Lifetime::new(PICKUP_LIFETIME),
+            run_entity: RunEntity,
+        }
+    }
+}
+
+pub fn spawn_pickup(kind: PickupKind, pos: Vec2, commands: &mut Commands, assets: &GameAssets) -> Entity {
+    let mut builder = commands.spawn(PickupBundle::new(kind, pos, assets));
+    // XP gems are a sparkle from the projectile sprite sheet.
+    if let PickupKind::Xp(_) = kind {
+        builder.insert(TextureAtlas {
+            layout: assets.projectile_atlas.clone(),
+            index: assets.projectile_indices.sparkle,
+        });
+    }
+    builder.id()
+}
+
+/// Drops several pickups around a spot, spread out so they don't stack on top of each other.
+pub fn spawn_drops(
+    kinds: impl IntoIterator<Item = PickupKind>,
+    pos: Vec2,
+    rng: &mut fastrand::Rng,
+    commands: &mut Commands,
+    assets: &GameAssets,
+) {
+    for (i, kind) in kinds.into_iter().enumerate() {
+        let offset = if i == 0 {
+            Vec2::ZERO
+        } else {
+            Mat2::from_angle(rng.f32() * std::f32::consts::TAU) * Vec2::X * DROP_SCATTER
+        };
+        spawn_pickup(kind, pos + offset, commands, assets);
+    }
+}
+
+fn drop_pickups(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    drop_tables: Res<Assets<DropTable>>,
+    mut rng: ResMut<GameRng>,
+    enemy_q: Query<(&EnemyKind, &GlobalTransform), Added<Death>>,
+) {
+    let Some(drop_table) = drop_tables.get(&assets.drops) else {
+        return;
+    };
+
+    for (kind, transform) in enemy_q.iter() {
+        let Some(drops) = drop_table.drops.get(kind) else {
+            continue;
+        };
+
+        let dropped: Vec<PickupKind> = drops.iter()
+            .filter(|drop| rng.drops.f32() < drop.chance)
+            .map(|drop| drop.pickup)
+            .collect();
+        if dropped.is_empty() {
+            continue;
+        }
+
+        trace!("{} dropped {:?}", kind.name(), dropped);
+        let pos = transform.translation().truncate();
+        spawn_drops(dropped, pos, &mut rng.drops, &mut commands, &assets);
+    }
+}
+
+fn magnetize_pickups(
+    time: Res<Time>,
+    mut pickup_q: Query<(&mut Pickup, &mut Transform), Without<Player>>,
+    player_q: Query<(&Transform, &PlayerHealth), With<Player>>,
+) {
+    let Ok((player_transform, health)) = player_q.get_single() else {
+        return;
+    };
+    if health.current == 0 {
+        return;
+    }
+
+    let player_pos = player_transform.translation.truncate();
+    let step = MAGNET_SPEED * time.delta_seconds();
+    for (mut pickup, mut transform) in pickup_q.iter_mut() {
+        let pos = transform.translation.truncate();
+        if !pickup.magnetized && pos.distance(player_pos) > MAGNET_RADIUS {
+            continue;
+        }
+        pickup.magnetized = true;
+
+        let pos = pos.move_towards(player_pos, step);
+        transform.translation.x = pos.x;
+        transform.translation.y = pos.y;
+    }
+}
+
+pub fn collect_pickups(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionEvent>,
+    pickup_q: Query<&Pickup>,
+    parent_q: Query<&Parent>,
+    mut player_q: Query<(&mut PlayerHealth, &mut Experience), With<Player>>,
+) {
+    for collision in collisions.read() {
+        let &CollisionEvent::Started(e1, e2, _flags) = collision else {
+            continue;
+        };
+        let (pickup_entity, other) = if pickup_q.contains(e1) { (e1, e2) } else { (e2, e1) };
+        let Ok(pickup) = pickup_q.get(pickup_entity) else {
+            continue;
+        };
+        // The player's collider is a child of the player.
+        let Ok((mut health, mut experience)) = parent_q.get(other)
+            .and_then(|parent| player_q.get_mut(parent.get())) else {
+            continue;
+        };
+        if health.current == 0 {
+            continue;
+        }
+
+        match pickup.kind {
+            PickupKind::Heart(hearts) => {
+                let gained = health.heal(health::half_hearts(hearts));
+                debug!("Picked up a heart, healed {} half hearts", gained);
+            }
+            PickupKind::HeartContainer => {
+                let gained = health.raise_max(HEART);
+                debug!("Picked up a heart container, max health raised by {} half hearts", gained);
+            }
+            PickupKind::Xp(xp) => {
+                experience.xp += xp;
+                trace!("Picked up {} XP", xp);
+            }
+        }
+        commands.entity(pickup_entity).despawn_recursive();
+    }
+}
+
+fn blink_expiring_pickups(
+    mut pickup_q: Query<(&Lifetime, &mut Visibility), With<Pickup>>,
+) {
+    for (lifetime, mut visibility) in pickup_q.iter_mut() {
+        if lifetime.remaining > PICKUP_BLINK_SECS {
+            continue;
+        }
+
+        let blink = (lifetime.remaining * PICKUP_BLINKS_PER_SEC * 2.0) as u32 % 2 == 0;
+        let new_visibility = if blink { Visibility::Inherited } else { Visibility::Hidden };
+        if *visibility != new_visibility {
+            *visibility = new_visibility;
+        }
+    }
+}
@@ -1,18 +1,24 @@
 use bevy::prelude::*;
+use bevy::ecs::system::SystemParam;
 use bevy::sprite::Anchor;
 use bevy::window::PrimaryWindow;
 use bevy_egui::EguiContexts;
 use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     AppState,
     animation::{self, Animation, AnimationState},
     assets::GameAssets,
     combat::*,
-    game::{Crosshair, Facing},
-    health::PlayerHealth,
+    game::{Crosshair, Facing, GameLogicSet, RunEntity},
+    health::{PlayerHealth, HEART},
+    interpolation::Interpolated,
     physics::{groups, ColliderBundle},
-    weapons::{Weapon, WeaponChoice, WeaponPlugin},
+    replay::not_playing_back,
+    status::StatusEffects,
+    upgrades::{Experience, Upgrades},
+    weapons::{EarlyReroll, HeldWeapon, Weapon, WeaponChoice, WeaponPlugin, WeaponsConfig},
     window::primary_window_exists,
 };
 
@@ -28,14 +34,16 @@ impl Plugin for PlayerPlugin {
             .register_type::<PlayerMovement>()
             .register_type::<PlayerInput>()
             .add_systems(Update, (
-                read_player_input.run_if(primary_window_exists),
-                update_player_movement.after(read_player_input),
-                update_player_sprite.after(update_player_movement),
+                read_player_input.run_if(primary_window_exists).run_if(not_playing_back),
+                update_player_sprite.after(read_player_input),
                 update_player_aim.after(read_player_input),
                 update_crosshair.after(update_player_aim),
-                update_post_hit_invuln,
-                apply_post_hit_invuln.after(deal_player_hit_damage),
             ).run_if(in_state(AppState::InGame)))
+            .add_systems(FixedUpdate, (
+                update_player_movement,
+                update_post_hit_invuln.before(deal_player_hit_damage),
+                apply_post_hit_invuln.after(deal_player_hit_damage),
+            ).in_set(GameLogicSet))
             .add_systems(PostUpdate, flicker_player_during_invuln);
     }
 }
@@ -44,6 +52,7 @@ pub fn spawn_player(
     pos: Vec2,
     commands: &mut Commands,
     assets: &GameAssets,
+    weapons: &WeaponsConfig,
 ) -> Entity {
     let crosshair_bundle = (
         SpriteBundle {
@@ -66,10 +75,11 @@ pub fn spawn_player(
         .id();
     commands.spawn(crosshair_bundle)
         .insert(Crosshair)
-        .insert(Name::new("MouseCrosshair"));
+        .insert(Name::new("MouseCrosshair"))
+        .insert(RunEntity);
 
     let groups = groups::PLAYER;
-    let masks = groups::WORLD;
+    let masks = groups::WORLD | groups::PICKUP;
     let collider = ColliderBundle::new(Vec2::new(11.0, 11.0), Vec2::ZERO, groups, masks);
     let collider = commands.spawn(collider)
         .insert(Name::new("PlayerCollider"))
@@ -80,11 +90,13 @@ pub fn spawn_player(
     let masks = groups::HIT;
     let hurt_box = ColliderBundle::new(Vec2::new(8.0, 8.0), Vec2::ZERO, groups, masks);
     let hurt_box = commands.spawn(hurt_box)
+        .insert(HurtBox)
         .insert(Name::new("PlayerHurtBox"))
         .insert(ActiveEvents::COLLISION_EVENTS)
         .id();
 
-    let player_bundle = PlayerBundle::new(pos, assets.player.clone(), assets.player_atlas.clone(), assets.player_anims.idle.clone());
+    let weapon = Weapon::new(WeaponChoice::default(), weapons, &Upgrades::default());
+    let player_bundle = PlayerBundle::new(pos, assets.player.clone(), assets.player_atlas.clone(), assets.player_anims.idle.clone(), weapon);
     commands.spawn(player_bundle)
         .insert(Player { hurt_box })
         .add_child(crosshair)
@@ -110,12 +122,19 @@ pub struct PlayerBundle {
     play: animation::Play,
     health: PlayerHealth,
     knockback: Knockback,
+    status: StatusEffects,
     weapon: Weapon,
+    held_weapon: HeldWeapon,
+    early_reroll: EarlyReroll,
+    experience: Experience,
+    upgrades: Upgrades,
     post_hit_invuln: PostHitInvulnerability,
+    interpolated: Interpolated,
+    run_entity: RunEntity,
 }
 
 impl PlayerBundle {
-    pub fn new(pos: Vec2, texture: Handle<Image>, atlas: Handle<TextureAtlasLayout>, anim: Handle<Animation>) -> Self {
+    pub fn new(pos: Vec2, texture: Handle<Image>, atlas: Handle<TextureAtlasLayout>, anim: Handle<Animation>, weapon: Weapon) -> Self {
         let pos = pos.extend(PLAYER_Z);
         Self {
             sprite: SpriteBundle {
@@ -141,10 +160,17 @@ impl PlayerBundle {
             movement: PlayerMovement { walk_speed: 100.0 },
             input: default(),
             play: animation::Play,
-            health: PlayerHealth::new(4),
+            health: PlayerHealth::new(4 * HEART),
             knockback: default(),
-            weapon: Weapon::new(WeaponChoice::default()),
+            status: default(),
+            weapon,
+            held_weapon: default(),
+            early_reroll: default(),
+            experience: default(),
+            upgrades: default(),
             post_hit_invuln: default(),
+            interpolated: default(),
+            run_entity: RunEntity,
         }
     }
 }
@@ -155,22 +181,25 @@ pub struct Player {
 }
 
 #[derive(Component, Reflect)]
-struct PlayerMovement {
+pub struct PlayerMovement {
     walk_speed: f32,
 }
 
 #[derive(Component, Reflect)]
 pub struct PlayerAim(pub Vec2);
 
-#[derive(Default, Component, Reflect)]
+#[derive(Clone, Default, PartialEq, Component, Reflect, Deserialize, Serialize)]
+#[serde(default)]
 pub struct PlayerInput {
     pub movement: Vec2,
     pub aim: Vec2,
     pub aim_device: AimDevice,
     pub shoot: bool,
+    /// Either one swaps the active weapon with the held one.
     pub next_weapon: bool,
     pub prev_weapon: bool,
-    pub reset_game: bool,
+    /// Throw away the remaining ammo and re-roll right away.
+    pub reroll: bool,
 }
 
 #[derive(Default, Component)]
@@ -179,11 +208,12 @@ pub struct PostHitInvulnerability {
 }
 
 impl PostHitInvulnerability {
-    fn is_active(&self) -> bool {
+    pub fn is_active(&self) -> bool {
         self.remaining > 0.0
     }
 
-    fn start(&mut self) {
+    /// Only taking damage starts it, not other changes to health like healing.
+    pub fn start(&mut self) {
         self.remaining = POST_HIT_INVULN;
     }
 
@@ -206,7 +236,7 @@ fn get_mouse_world_pos(
         .and_then(|cursor_pos| camera.viewport_to_world_2d(camera_transform, cursor_pos))
 }
 
-#[derive(Clone, Copy, Default, Reflect)]
+#[derive(Clone, Copy, Default, PartialEq, Reflect, Deserialize, Serialize)]
 pub enum AimDevice {
     #[default]
     None,
@@ -214,25 +244,34 @@ pub enum AimDevice {
     Mouse(Vec2),
 }
 
+/// Buttons and sticks the player can be controlled with.
+#[derive(SystemParam)]
+pub struct InputDevices<'w> {
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    mouse_buttons: Res<'w, ButtonInput<MouseButton>>,
+    gamepads: Res<'w, Gamepads>,
+    pad_buttons: Res<'w, ButtonInput<GamepadButton>>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+}
+
 pub fn read_player_input(
-    keys: Res<ButtonInput<KeyCode>>,
-    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    devices: InputDevices,
     mut cursor_moved: EventReader<CursorMoved>,
-    gamepads: Res<Gamepads>,
-    pad_buttons: Res<ButtonInput<GamepadButton>>,
-    axes: Res<Axis<GamepadAxis>>,
     mut egui_ctx: EguiContexts,
     mut player_q: Query<(&mut PlayerInput, &GlobalTransform)>,
     camera_q: Query<(&Camera, &GlobalTransform)>,
     primary_window_q: Query<&Window, With<PrimaryWindow>>,
 ) {
+    let InputDevices { keys, mouse_buttons, gamepads, pad_buttons, axes } = devices;
     let (mut input, player_transform) = player_q.single_mut();
 
     let mut movement = Vec2::ZERO;
     let mut aim = Vec2::ZERO;
     let mut aim_device = input.aim_device;
     let mut shoot = false;
-    let mut reset_game = false;
+    let mut next_weapon = false;
+    let mut prev_weapon = false;
+    let mut reroll = false;
 
     // Read input from gamepad.
     if let Some(gamepad) = gamepads.iter().next() {
@@ -265,8 +304,13 @@ pub fn read_player_input(
         let shoot_button = GamepadButton::new(gamepad, GamepadButtonType::RightTrigger2);
         shoot |= pad_buttons.pressed(shoot_button);
 
-        let reset_button = GamepadButton::new(gamepad, GamepadButtonType::Start);
-        reset_game |= pad_buttons.pressed(reset_button);
+        // Swap weapons
+        next_weapon |= pad_buttons.pressed(GamepadButton::new(gamepad, GamepadButtonType::RightTrigger));
+        prev_weapon |= pad_buttons.pressed(GamepadButton::new(gamepad, GamepadButtonType::LeftTrigger));
+
+        // Re-roll. The face buttons pick level up options, so one still held when play resumes
+        // would throw away the magazine.
+        reroll |= pad_buttons.pressed(GamepadButton::new(gamepad, GamepadButtonType::LeftTrigger2));
     }
 
     // Read input from mouse/keyboard.
@@ -291,20 +335,39 @@ pub fn read_player_input(
     // Shoot
     shoot |= mouse_buttons.pressed(MouseButton::Left) && !egui_ctx.ctx_mut().wants_pointer_input();
 
-    reset_game |= keys.just_pressed(KeyCode::Space) && !egui_ctx.ctx_mut().wants_keyboard_input();
+    // Swap weapons and re-roll
+    if !egui_ctx.ctx_mut().wants_keyboard_input() {
+        next_weapon |= keys.pressed(KeyCode::KeyE);
+        prev_weapon |= keys.pressed(KeyCode::KeyQ);
+        reroll |= keys.pressed(KeyCode::KeyR);
+    }
 
     // Store results in player input component.
     input.movement = movement;
     input.aim = aim;
     input.aim_device = aim_device;
     input.shoot = shoot;
-    input.reset_game = reset_game;
+    input.next_weapon = next_weapon;
+    input.prev_weapon = prev_weapon;
+    input.reroll = reroll;
 }
 
-fn update_player_movement(
-    mut q: Query<(&PlayerMovement, &PlayerInput, &mut Velocity, &mut Facing, &Knockback, &PlayerHealth)>,
+/// Everything that decides how fast and which way the player moves.
+type MovingPlayer = (
+    &'static PlayerMovement,
+    &'static Upgrades,
+    &'static PlayerInput,
+    &'static mut Velocity,
+    &'static mut Facing,
+    &'static Knockback,
+    &'static StatusEffects,
+    &'static PlayerHealth,
+);
+
+pub fn update_player_movement(
+    mut q: Query<MovingPlayer>,
 ) {
-    for (movement, input, mut velocity, mut facing, knockback, health) in q.iter_mut() {
+    for (movement, upgrades, input, mut velocity, mut facing, knockback, status, health) in q.iter_mut() {
         if knockback.is_active() {
             continue;
         }
@@ -312,7 +375,7 @@ fn update_player_movement(
         if health.current == 0 {
             velocity.linvel = Vec2::ZERO;
         } else {
-            velocity.linvel = input.movement * movement.walk_speed;
+            velocity.linvel = input.movement * movement.walk_speed * upgrades.move_speed_multiplier * status.speed_multiplier();
         }
 
         if input.movement != Vec2::ZERO {
@@ -348,9 +411,14 @@ fn update_player_sprite(
     }
 }
 
+/// The gamepad crosshair is a child of the player, so it follows them around.
+type GamepadCrosshair = (With<Crosshair>, With<Parent>);
+/// The mouse crosshair sits wherever the cursor is in the world.
+type MouseCrosshair = (With<Crosshair>, Without<Parent>);
+
 fn update_crosshair(
-    mut gamepad_crosshair_q: Query<(&mut Transform, &mut Visibility), (With<Crosshair>, With<Parent>)>,
-    mut mouse_crosshair_q: Query<(&mut Transform, &mut Visibility), (With<Crosshair>, Without<Parent>)>,
+    mut gamepad_crosshair_q: Query<(&mut Transform, &mut Visibility), GamepadCrosshair>,
+    mut mouse_crosshair_q: Query<(&mut Transform, &mut Visibility), MouseCrosshair>,
     input_q: Query<&PlayerInput>,
 ) {
     if let Ok(input) = input_q.get_single() {
@@ -377,37 +445,34 @@ fn update_crosshair(
     }
 }
 
+/// Clears the hurt box collision layers while invulnerable, and puts them back afterwards.
 fn apply_post_hit_invuln(
-    mut player_q: Query<(&Player, &mut PostHitInvulnerability, &PlayerHealth), Changed<PlayerHealth>>,
+    player_q: Query<(&Player, &PostHitInvulnerability)>,
     mut hurt_box_q: Query<&mut CollisionGroups>,
 ) {
-    for (player, mut invuln, health) in player_q.iter_mut() {
-        if health.current > 0 && health.missing() > 0 {
-            invuln.start();
-
-            // Invulnerability started, clear hurt box collision layers.
-            if let Ok(mut layers) = hurt_box_q.get_mut(player.hurt_box) {
-                *layers = CollisionGroups::new(Group::NONE, Group::NONE);
-            }
+    for (player, invuln) in player_q.iter() {
+        let Ok(mut layers) = hurt_box_q.get_mut(player.hurt_box) else {
+            continue;
+        };
+        let wanted = if invuln.is_active() {
+            CollisionGroups::new(Group::NONE, Group::NONE)
+        } else {
+            CollisionGroups::new(groups::PLAYER, groups::HIT)
+        };
+        if *layers != wanted {
+            *layers = wanted;
         }
     }
 }
 
 fn update_post_hit_invuln(
     time: Res<Time>,
-    mut player_q: Query<(&Player, &mut PostHitInvulnerability)>,
-    mut hurt_box_q: Query<&mut CollisionGroups>,
+    mut invuln_q: Query<&mut PostHitInvulnerability>,
 ) {
     let dt = time.delta_seconds();
-    for (player, mut invuln) in player_q.iter_mut() {
+    for mut invuln in invuln_q.iter_mut() {
         if invuln.is_active() {
             invuln.tick(dt);
-            if !invuln.is_active() {
-                // Invulnerability ended, reset hurt box collision layers.
-                if let Ok(mut layers) = hurt_box_q.get_mut(player.hurt_box) {
-                    *layers = CollisionGroups::new(groups::PLAYER, groups::HIT);
-                }
-            }
         }
     }
 }
@@ -0,0 +1,246 @@
+use std::fs;
+
+use bevy::prelude::*;
+use bevy::app::AppExit;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AppState, InRun,
+    game::RunStarted,
+    player::PlayerInput,
+    rng::GameRng,
+    upgrades::UpgradeChosen,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+const REPLAY_FILENAME: &str = "last_run.replay.ron";
+
+/// Records the player's input every fixed timestep so a run can be played back exactly.
+///
+/// The last run is saved to `last_run.replay.ron` when a new run starts or the game exits. Pass
+/// `--replay <file>` on the command line to play one back.
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some((path, replay)) = crate::arg_value("--replay")
+            .and_then(|path| load_replay(&path).map(|replay| (path, replay))) {
+            info!("Playing back replay {} with seed {}", path, replay.seed);
+            app
+                .insert_resource(GameRng::new(replay.seed))
+                .insert_resource(ReplayPlayback::new(replay));
+        }
+
+        app
+            .init_resource::<ReplayRecorder>()
+            .add_systems(FixedPreUpdate, (
+                play_back_input.run_if(resource_exists::<ReplayPlayback>),
+                record_input.run_if(not(resource_exists::<ReplayPlayback>)),
+            ).run_if(in_state(InRun)))
+            .add_systems(OnEnter(AppState::LevelUp), play_back_upgrade_choice.run_if(resource_exists::<ReplayPlayback>))
+            .add_systems(Update, record_upgrade_choices.run_if(not(resource_exists::<ReplayPlayback>)));
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            app.add_systems(Last, save_replay_on_exit.run_if(on_event::<AppExit>()));
+        }
+    }
+}
+
+/// Run condition for systems that read input from devices, which we don't want to do while
+/// playing back a replay.
+pub fn not_playing_back(
+    playback: Option<Res<ReplayPlayback>>,
+) -> bool {
+    playback.is_none()
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct Replay {
+    pub seed: u64,
+    /// Player input for each fixed timestep, run-length encoded since input rarely changes
+    /// every timestep.
+    pub inputs: Vec<(u32, PlayerInput)>,
+    /// The option picked on each level up screen, in order.
+    #[serde(default)]
+    pub upgrade_choices: Vec<usize>,
+}
+
+impl Replay {
+    fn push(&mut self, input: &PlayerInput) {
+        match self.inputs.last_mut() {
+            Some((count, last)) if last == input => *count += 1,
+            _ => self.inputs.push((1, input.clone())),
+        }
+    }
+
+    fn len(&self) -> u32 {
+        self.inputs.iter().map(|(count, _)| count).sum()
+    }
+}
+
+#[derive(Default, Resource)]
+struct ReplayRecorder {
+    /// Only record from the start of a run.
+    recording: bool,
+    replay: Replay,
+}
+
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    replay: Replay,
+    /// Index into the run-length encoded inputs.
+    index: usize,
+    /// How many timesteps of the current input have been played.
+    played: u32,
+    /// Index into the upgrade choices.
+    next_choice: usize,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            index: 0,
+            played: 0,
+            next_choice: 0,
+        }
+    }
+
+    fn started(&self) -> bool {
+        self.index > 0 || self.played > 0
+    }
+
+    fn next_input(&mut self) -> Option<&PlayerInput> {
+        let (count, _) = self.replay.inputs.get(self.index)?;
+        if self.played >= *count {
+            self.index += 1;
+            self.played = 0;
+        }
+        let (_, input) = self.replay.inputs.get(self.index)?;
+        self.played += 1;
+        Some(input)
+    }
+}
+
+/// Returns `None` if the replay can't be loaded, so the game starts a normal run instead.
+fn load_replay(path: &str) -> Option<Replay> {
+    let replay_str = match fs::read_to_string(path) {
+        Ok(replay_str) => replay_str,
+        Err(e) => {
+            warn!("Could not read replay file {}, starting a normal run: {}", path, e);
+            return None;
+        }
+    };
+    match ron::from_str(&replay_str) {
+        Ok(replay) => Some(replay),
+        Err(e) => {
+            warn!("Could not deserialize replay {}, starting a normal run: {}", path, e);
+            None
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn save_replay(replay: &Replay) {
+    if replay.inputs.is_empty() {
+        return;
+    }
+
+    info!("Saving replay of run with seed {} ({} timesteps)", replay.seed, replay.len());
+
+    let replay_str = match ron::ser::to_string(replay) {
+        Ok(replay_str) => replay_str,
+        Err(e) => {
+            warn!("Could not serialize replay: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = fs::write(REPLAY_FILENAME, replay_str) {
+        warn!("Could not write replay to file: {}", e);
+    }
+}
+
+fn record_input(
+    mut run_started: EventReader<RunStarted>,
+    mut recorder: ResMut<ReplayRecorder>,
+    rng: Res<GameRng>,
+    input_q: Query<&PlayerInput>,
+) {
+    if run_started.read().count() > 0 {
+        // Previous run is over, so save it and start recording the new one.
+        #[cfg(not(target_arch = "wasm32"))]
+        save_replay(&recorder.replay);
+
+        recorder.recording = true;
+        recorder.replay = Replay {
+            seed: rng.seed(),
+            ..default()
+        };
+    }
+
+    if !recorder.recording {
+        return;
+    }
+
+    if let Ok(input) = input_q.get_single() {
+        recorder.replay.push(input);
+    }
+}
+
+fn play_back_input(
+    mut commands: Commands,
+    mut run_started: EventReader<RunStarted>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut input_q: Query<&mut PlayerInput>,
+) {
+    // Replays only cover a single run.
+    let new_run = run_started.read().count() > 0;
+    let next_input = if new_run && playback.started() {
+        None
+    } else {
+        playback.next_input().cloned()
+    };
+
+    match next_input {
+        Some(next_input) => {
+            if let Ok(mut input) = input_q.get_single_mut() {
+                *input = next_input;
+            }
+        }
+        None => {
+            info!("Replay finished");
+            commands.remove_resource::<ReplayPlayback>();
+        }
+    }
+}
+
+fn record_upgrade_choices(
+    mut recorder: ResMut<ReplayRecorder>,
+    mut chosen: EventReader<UpgradeChosen>,
+) {
+    for &UpgradeChosen(option) in chosen.read() {
+        if recorder.recording {
+            recorder.replay.upgrade_choices.push(option);
+        }
+    }
+}
+
+fn play_back_upgrade_choice(
+    mut playback: ResMut<ReplayPlayback>,
+    mut chosen: EventWriter<UpgradeChosen>,
+) {
+    let Some(&option) = playback.replay.upgrade_choices.get(playback.next_choice) else {
+        warn!("Replay has no upgrade choice for this level up, picking the first one.");
+        chosen.send(UpgradeChosen(0));
+        return;
+    };
+    playback.next_choice += 1;
+    chosen.send(UpgradeChosen(option));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn save_replay_on_exit(
+    recorder: Res<ReplayRecorder>,
+) {
+    save_replay(&recorder.replay);
+}
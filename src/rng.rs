@@ -0,0 +1,90 @@
+use bevy::prelude::*;
+
+/// Random number generators for gameplay, all derived from a single run seed so a run can be
+/// reproduced exactly.
+///
+/// Each system gets its own stream so that e.g. firing an extra shot doesn't change where enemies
+/// spawn.
+#[derive(Resource)]
+pub struct GameRng {
+    seed: u64,
+    pub weapons: fastrand::Rng,
+    pub spawning: fastrand::Rng,
+    pub drops: fastrand::Rng,
+    pub upgrades: fastrand::Rng,
+    pub crits: fastrand::Rng,
+    terrain_seed: u64,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            weapons: fastrand::Rng::with_seed(stream_seed(seed, 1)),
+            spawning: fastrand::Rng::with_seed(stream_seed(seed, 2)),
+            terrain_seed: stream_seed(seed, 3),
+            drops: fastrand::Rng::with_seed(stream_seed(seed, 4)),
+            upgrades: fastrand::Rng::with_seed(stream_seed(seed, 5)),
+            crits: fastrand::Rng::with_seed(stream_seed(seed, 6)),
+        }
+    }
+
+    /// Start a run with the seed passed with `--seed` on the command line, or a random one.
+    pub fn from_args_or_random() -> Self {
+        Self::new(seed_from_args().unwrap_or_else(random_seed))
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Terrain gets a generator per chunk, so chunks look the same no matter what order the
+    /// player explores them in.
+    pub fn terrain_chunk(&self, chunk_pos: IVec2) -> fastrand::Rng {
+        let chunk_bits = ((chunk_pos.x as u32 as u64) << 32) | (chunk_pos.y as u32 as u64);
+        fastrand::Rng::with_seed(stream_seed(self.terrain_seed, chunk_bits))
+    }
+}
+
+/// Picks an item with a chance proportional to its weight, or none if no item has any weight.
+pub fn pick_weighted<T: Copy>(items: &[(T, f32)], rng: &mut fastrand::Rng) -> Option<T> {
+    let total: f32 = items.iter().map(|(_, weight)| weight.max(0.0)).sum();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let mut roll = rng.f32() * total;
+    for &(item, weight) in items.iter() {
+        let weight = weight.max(0.0);
+        if roll < weight {
+            return Some(item);
+        }
+        roll -= weight;
+    }
+    // Rounding can leave the roll just past the end.
+    items.iter()
+        .rev()
+        .find(|(_, weight)| *weight > 0.0)
+        .map(|(item, _)| *item)
+}
+
+pub fn random_seed() -> u64 {
+    fastrand::u64(..)
+}
+
+fn seed_from_args() -> Option<u64> {
+    let seed = crate::arg_value("--seed")?;
+    let parsed = seed.parse().ok();
+    if parsed.is_none() {
+        warn!("Invalid seed \"{}\", using a random one instead.", seed);
+    }
+    parsed
+}
+
+/// Mix a stream id into a seed using SplitMix64, so streams from nearby seeds don't correlate.
+fn stream_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,89 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Saved next to the window state on native, or under this key in local storage on web.
+const SETTINGS_FILENAME: &str = "settings.ron";
+
+/// Loads the player's saved settings. Headless simulations skip this and use the defaults.
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_settings());
+    }
+}
+
+/// Options the player can change from the menus.
+#[derive(Clone, Debug, Deserialize, Serialize, Resource)]
+pub struct Settings {
+    /// Show how much damage each hit deals.
+    #[serde(default = "default_true")]
+    pub damage_numbers: bool,
+    /// Some players find camera shake uncomfortable.
+    #[serde(default = "default_true")]
+    pub screen_shake: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            damage_numbers: true,
+            screen_shake: true,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn load_settings() -> Settings {
+    let Some(settings_str) = read_settings() else {
+        return default();
+    };
+    ron::from_str(&settings_str).unwrap_or_else(|e| {
+        warn!("Could not deserialize settings, using the defaults: {}", e);
+        default()
+    })
+}
+
+/// Called whenever a setting changes, since browsers don't let us save on exit.
+pub fn save_settings(settings: &Settings) {
+    info!("Saving settings");
+
+    let pretty_config = ron::ser::PrettyConfig::default();
+    let settings_str = ron::ser::to_string_pretty(settings, pretty_config)
+        .expect("Could not serialize settings");
+    write_settings(&settings_str);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_settings() -> Option<String> {
+    std::fs::read_to_string(SETTINGS_FILENAME).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_settings(settings_str: &str) {
+    if let Err(e) = std::fs::write(SETTINGS_FILENAME, settings_str) {
+        warn!("Could not write settings to file: {}", e);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_settings() -> Option<String> {
+    local_storage()?.get_item(SETTINGS_FILENAME).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_settings(settings_str: &str) {
+    let saved = local_storage()
+        .is_some_and(|storage| storage.set_item(SETTINGS_FILENAME, settings_str).is_ok());
+    if !saved {
+        warn!("Could not write settings to local storage");
+    }
+}
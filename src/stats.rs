@@ -0,0 +1,123 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+
+use crate::{
+    combat::{self, HitEvent},
+    enemies::{self, boss::Boss, spawner::EnemyCount, Death, Enemy, EnemyKind},
+    health::{PlayerHealth, HEART},
+    game::GameLogicSet,
+    player::Player,
+    weapons::{FiredBy, WeaponChoice},
+};
+
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<RunStats>()
+            .add_systems(FixedUpdate, (
+                record_hits.after(combat::check_hits),
+                record_player_hits
+                    .after(combat::check_hits)
+                    .before(combat::deal_player_hit_damage),
+                record_kills
+                    .after(enemies::trigger_enemy_death)
+                    .before(enemies::despawn_dead_enemies),
+                record_peak_enemy_count,
+            ).in_set(GameLogicSet));
+    }
+}
+
+/// Stats for the current run, shown on the game over screen.
+#[derive(Default, Resource)]
+pub struct RunStats {
+    pub kills: HashMap<EnemyKind, u32>,
+    pub damage_dealt: HashMap<WeaponChoice, f32>,
+    pub shots_fired: u32,
+    /// Shots where at least one projectile hit an enemy.
+    shots_hit: HashSet<u32>,
+    pub rerolls: u32,
+    /// In hearts.
+    pub damage_taken: f32,
+    pub peak_enemy_count: u32,
+    pub bosses_killed: u32,
+}
+
+impl RunStats {
+    /// Returns the number of the new shot.
+    pub fn record_shot(&mut self) -> u32 {
+        self.shots_fired += 1;
+        self.shots_fired
+    }
+
+    pub fn shots_hit(&self) -> u32 {
+        self.shots_hit.len() as u32
+    }
+
+    pub fn accuracy(&self) -> f32 {
+        if self.shots_fired == 0 {
+            return 0.0;
+        }
+        self.shots_hit() as f32 / self.shots_fired as f32
+    }
+
+    pub fn total_kills(&self) -> u32 {
+        self.kills.values().sum()
+    }
+
+    /// Called where hits deal damage, so crits count for their full damage.
+    pub fn record_damage(&mut self, fired_by: &FiredBy, damage: f32) {
+        *self.damage_dealt.entry(fired_by.weapon).or_default() += damage;
+    }
+}
+
+fn record_hits(
+    mut stats: ResMut<RunStats>,
+    mut hits: EventReader<HitEvent>,
+    fired_by_q: Query<&FiredBy>,
+    enemy_q: Query<(), With<Enemy>>,
+) {
+    for hit in hits.read() {
+        if !enemy_q.contains(hit.defender) {
+            continue;
+        }
+
+        if let Ok(fired_by) = fired_by_q.get(hit.attacker) {
+            stats.shots_hit.insert(fired_by.shot);
+        }
+    }
+}
+
+fn record_player_hits(
+    mut stats: ResMut<RunStats>,
+    mut hits: EventReader<HitEvent>,
+    player_q: Query<(Entity, &PlayerHealth), With<Player>>,
+) {
+    let Ok((player, health)) = player_q.get_single() else {
+        return;
+    };
+    if health.current == 0 {
+        return;
+    }
+    stats.damage_taken += combat::player_hit_damage(hits.read(), player) as f32 / HEART as f32;
+}
+
+fn record_kills(
+    mut stats: ResMut<RunStats>,
+    killed_q: Query<&EnemyKind, Added<Death>>,
+    killed_boss_q: Query<(), (With<Boss>, Added<Death>)>,
+) {
+    for kind in killed_q.iter() {
+        *stats.kills.entry(*kind).or_default() += 1;
+    }
+    stats.bosses_killed += killed_boss_q.iter().count() as u32;
+}
+
+fn record_peak_enemy_count(
+    mut stats: ResMut<RunStats>,
+    enemy_count: Res<EnemyCount>,
+) {
+    stats.peak_enemy_count = stats.peak_enemy_count.max(enemy_count.0);
+}
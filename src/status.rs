@@ -0,0 +1,245 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    combat::{self, HitEvent},
+    enemies::{self, AiRanged},
+    game::GameLogicSet,
+    health::{EnemyHealth, HEART},
+    player::Player,
+};
+
+/// How many stacks of poison can tick at once.
+const MAX_POISON_STACKS: usize = 5;
+const BURN_COLOR: Color = Color::srgb(1.0, 0.55, 0.3);
+const POISON_COLOR: Color = Color::srgb(0.5, 1.0, 0.4);
+const SLOW_COLOR: Color = Color::srgb(0.6, 0.7, 1.0);
+const FREEZE_COLOR: Color = Color::srgb(0.5, 0.9, 1.0);
+const STUN_COLOR: Color = Color::srgb(1.0, 1.0, 0.5);
+
+/// Burning, poison, slows and stuns that hits can leave behind.
+pub struct StatusPlugin;
+
+impl Plugin for StatusPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<StatusEffects>()
+            .add_systems(FixedUpdate, (
+                apply_hit_statuses.after(combat::check_hits),
+                tick_status_effects
+                    .after(apply_hit_statuses)
+                    .before(combat::deal_player_hit_damage)
+                    .before(enemies::trigger_enemy_death),
+                color_sprites
+                    .after(tick_status_effects)
+                    .after(enemies::ranged_ai),
+            ).in_set(GameLogicSet));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Reflect)]
+pub enum StatusKind {
+    /// Deals `strength` damage per second. Hitting again refreshes it, keeping the stronger burn.
+    Burn,
+    /// Deals `strength` damage per second. Each hit adds a separate stack with its own duration.
+    Poison,
+    /// Takes `strength` (0 to 1) off movement speed. Hitting again refreshes it, keeping the
+    /// stronger slow.
+    Slow,
+    /// Stops movement entirely. Hitting again refreshes it.
+    Freeze,
+    /// Stops movement and attacks. Hitting again refreshes it.
+    Stun,
+}
+
+impl StatusKind {
+    fn color(&self) -> Color {
+        match self {
+            Self::Burn => BURN_COLOR,
+            Self::Poison => POISON_COLOR,
+            Self::Slow => SLOW_COLOR,
+            Self::Freeze => FREEZE_COLOR,
+            Self::Stun => STUN_COLOR,
+        }
+    }
+}
+
+/// A status effect a hit applies.
+#[derive(Clone, Debug, Deserialize)]
+pub struct StatusEffectSpec {
+    pub kind: StatusKind,
+    /// In seconds.
+    pub duration: f32,
+    /// What this means depends on the kind. Unused by freeze and stun.
+    #[serde(default)]
+    pub strength: f32,
+}
+
+#[derive(Clone, Debug, Reflect)]
+struct ActiveEffect {
+    kind: StatusKind,
+    remaining: f32,
+    strength: f32,
+}
+
+/// Status effects currently on an enemy or the player. Anything without this, like bosses, shrugs
+/// them off.
+#[derive(Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct StatusEffects {
+    effects: Vec<ActiveEffect>,
+    /// Damage over time that hasn't been dealt yet, for things that only take whole amounts of
+    /// damage, like the player.
+    pending_damage: f32,
+}
+
+impl StatusEffects {
+    pub fn apply(&mut self, spec: &StatusEffectSpec) {
+        let new_effect = ActiveEffect {
+            kind: spec.kind,
+            remaining: spec.duration,
+            strength: spec.strength,
+        };
+
+        if spec.kind == StatusKind::Poison {
+            let stacks = self.effects.iter().filter(|effect| effect.kind == StatusKind::Poison).count();
+            if stacks < MAX_POISON_STACKS {
+                self.effects.push(new_effect);
+                return;
+            }
+            // Out of stacks, so replace the one closest to running out.
+            if let Some(oldest) = self.effects.iter_mut()
+                .filter(|effect| effect.kind == StatusKind::Poison)
+                .min_by(|a, b| a.remaining.total_cmp(&b.remaining)) {
+                *oldest = new_effect;
+            }
+            return;
+        }
+
+        match self.effects.iter_mut().find(|effect| effect.kind == spec.kind) {
+            Some(effect) => {
+                effect.remaining = effect.remaining.max(spec.duration);
+                effect.strength = effect.strength.max(spec.strength);
+            }
+            None => self.effects.push(new_effect),
+        }
+    }
+
+    pub fn has(&self, kind: StatusKind) -> bool {
+        self.effects.iter().any(|effect| effect.kind == kind)
+    }
+
+    pub fn is_stunned(&self) -> bool {
+        self.has(StatusKind::Stun)
+    }
+
+    /// What to multiply movement speed by.
+    pub fn speed_multiplier(&self) -> f32 {
+        if self.has(StatusKind::Freeze) || self.is_stunned() {
+            return 0.0;
+        }
+        let slow = self.effects.iter()
+            .filter(|effect| effect.kind == StatusKind::Slow)
+            .map(|effect| effect.strength)
+            .fold(0.0, f32::max);
+        (1.0 - slow).clamp(0.0, 1.0)
+    }
+
+    pub fn damage_per_second(&self) -> f32 {
+        self.effects.iter()
+            .filter(|effect| matches!(effect.kind, StatusKind::Burn | StatusKind::Poison))
+            .map(|effect| effect.strength)
+            .sum()
+    }
+
+    /// The color of the most severe effect.
+    fn wanted_tint(&self) -> Option<Color> {
+        [StatusKind::Freeze, StatusKind::Stun, StatusKind::Burn, StatusKind::Poison, StatusKind::Slow]
+            .into_iter()
+            .find(|&kind| self.has(kind))
+            .map(|kind| kind.color())
+    }
+
+    /// Counts down every effect and returns how much damage over time they dealt.
+    fn tick(&mut self, dt: f32) -> f32 {
+        let damage = self.damage_per_second() * dt;
+        for effect in self.effects.iter_mut() {
+            effect.remaining -= dt;
+        }
+        self.effects.retain(|effect| effect.remaining > 0.0);
+        if self.effects.is_empty() {
+            self.pending_damage = 0.0;
+        }
+        damage
+    }
+}
+
+fn apply_hit_statuses(
+    mut hits: EventReader<HitEvent>,
+    mut status_q: Query<&mut StatusEffects>,
+) {
+    for hit in hits.read() {
+        if hit.statuses.is_empty() {
+            continue;
+        }
+        let Ok(mut status) = status_q.get_mut(hit.defender) else {
+            continue;
+        };
+        for spec in hit.statuses.iter() {
+            status.apply(spec);
+        }
+    }
+}
+
+/// Enemies lose health straight away. The player only takes damage in half hearts, so theirs builds
+/// up until it's worth a hit, and goes through the usual hit handling.
+fn tick_status_effects(
+    time: Res<Time>,
+    mut hits: EventWriter<HitEvent>,
+    mut status_q: Query<(Entity, &mut StatusEffects, Option<&mut EnemyHealth>, Has<Player>)>,
+) {
+    let dt = time.delta_seconds();
+    let half_heart = 1.0 / HEART as f32;
+    for (entity, mut status, enemy_health, is_player) in status_q.iter_mut() {
+        if status.effects.is_empty() {
+            continue;
+        }
+
+        let damage = status.tick(dt);
+        if let Some(mut health) = enemy_health {
+            health.lose_health(damage);
+        } else if is_player {
+            status.pending_damage += damage;
+            if status.pending_damage >= half_heart {
+                let hit_damage = (status.pending_damage / half_heart).floor() * half_heart;
+                status.pending_damage -= hit_damage;
+                hits.send(HitEvent {
+                    attacker: entity,
+                    defender: entity,
+                    damage: hit_damage,
+                    knockback: None,
+                    statuses: Vec::new(),
+                    crit_chance: 0.0,
+                    crit_multiplier: 1.0,
+                });
+            }
+        }
+    }
+}
+
+/// Works out the sprite color from scratch every step, so nothing else that colors the sprite can
+/// leave a stale tint behind. A ranged enemy's wind-up glow comes first, since the player needs to
+/// see the shot coming, then the tint of the most severe effect. Alpha is left alone for phasing.
+fn color_sprites(
+    mut sprite_q: Query<(&StatusEffects, &mut Sprite, Option<&AiRanged>)>,
+) {
+    for (status, mut sprite, ranged) in sprite_q.iter_mut() {
+        let color = ranged.and_then(AiRanged::telegraph_color)
+            .or_else(|| status.wanted_tint())
+            .unwrap_or(Color::WHITE)
+            .with_alpha(sprite.color.alpha());
+        if sprite.color != color {
+            sprite.color = color;
+        }
+    }
+}
@@ -5,6 +5,8 @@ use bevy::prelude::*;
 use crate::{
     AppState,
     assets::GameAssets,
+    game::RunEntity,
+    rng::GameRng,
 };
 
 const CHUNK_SIZE: f32 = 400.0;
@@ -30,6 +32,7 @@ struct ChunkBundle {
     chunk: Chunk,
     name: Name,
     spatial: SpatialBundle,
+    run_entity: RunEntity,
 }
 
 impl ChunkBundle {
@@ -40,6 +43,7 @@ impl ChunkBundle {
             chunk: Chunk,
             name: Name::new(format!("Chunk({}, {})", chunk_pos.x, chunk_pos.y)),
             spatial: SpatialBundle::from_transform(transform),
+            run_entity: RunEntity,
         }
     }
 }
@@ -48,15 +52,17 @@ fn spawn_single_chunk(
     chunk_pos: IVec2,
     commands: &mut Commands,
     assets: &GameAssets,
+    rng: &GameRng,
 ) -> Entity {
+    let mut rng = rng.terrain_chunk(chunk_pos);
     commands.spawn(ChunkBundle::new(chunk_pos))
         .with_children(|cb| {
             // Spawn grasses.
-            let num_grasses = fastrand::u8(15..20);
+            let num_grasses = rng.u8(15..20);
             for _ in 0..num_grasses {
-                let x = fastrand::f32() * CHUNK_SIZE;
-                let y = fastrand::f32() * CHUNK_SIZE;
-                let index = assets.terrain_indices.grass[fastrand::usize(0..assets.terrain_indices.grass.len())];
+                let x = rng.f32() * CHUNK_SIZE;
+                let y = rng.f32() * CHUNK_SIZE;
+                let index = assets.terrain_indices.grass[rng.usize(0..assets.terrain_indices.grass.len())];
                 let bundle = (
                     SpriteBundle {
                         sprite: Sprite {
@@ -77,11 +83,11 @@ fn spawn_single_chunk(
             }
 
             // Spawn dirt.
-            let num_dirt = fastrand::u8(8..14);
+            let num_dirt = rng.u8(8..14);
             for _ in 0..num_dirt {
-                let x = fastrand::f32() * CHUNK_SIZE;
-                let y = fastrand::f32() * CHUNK_SIZE;
-                let index = assets.terrain_indices.dirt[fastrand::usize(0..assets.terrain_indices.dirt.len())];
+                let x = rng.f32() * CHUNK_SIZE;
+                let y = rng.f32() * CHUNK_SIZE;
+                let index = assets.terrain_indices.dirt[rng.usize(0..assets.terrain_indices.dirt.len())];
                 let bundle = (
                     SpriteBundle {
                         sprite: Sprite {
@@ -110,6 +116,7 @@ pub fn spawn_missing_chunks(
     center_chunk: IVec2,
     commands: &mut Commands,
     assets: &GameAssets,
+    rng: &GameRng,
     spawned_chunks: &mut SpawnedChunks,
 ) {
     for j in -1..=1 {
@@ -117,7 +124,7 @@ pub fn spawn_missing_chunks(
             let chunk_pos = center_chunk + IVec2::new(i, j);
             spawned_chunks.0.entry(chunk_pos).or_insert_with(|| {
                 debug!("Spawning chunk at {}", chunk_pos);
-                spawn_single_chunk(chunk_pos, commands, assets)
+                spawn_single_chunk(chunk_pos, commands, assets, rng)
             });
         }
     }
@@ -127,6 +134,7 @@ fn spawn_chunks(
     mut commands: Commands,
     mut last_chunk: Local<IVec2>,
     assets: Res<GameAssets>,
+    rng: Res<GameRng>,
     mut spawned_chunks: ResMut<SpawnedChunks>,
     camera_q: Query<&GlobalTransform, With<Camera>>,
 ) {
@@ -139,7 +147,7 @@ fn spawn_chunks(
         let current_chunk = (camera_pos / CHUNK_SIZE).floor().as_ivec2();
         if current_chunk != *last_chunk {
             debug!("Camera entered new chunk (last: {}, current: {}, pos: {}), spawning missing chunks.", *last_chunk, current_chunk, camera_pos);
-            spawn_missing_chunks(current_chunk, &mut commands, &assets, &mut spawned_chunks);
+            spawn_missing_chunks(current_chunk, &mut commands, &assets, &rng, &mut spawned_chunks);
             // Update last chunk.
             *last_chunk = current_chunk;
         }
@@ -1,14 +1,18 @@
+use std::collections::HashMap;
+
 use bevy::prelude::*;
 use bevy_egui::{EguiContexts, EguiSettings};
 use bevy_egui::egui::{self, load::SizedTexture};
 
 use crate::{
-    AppState,
-    assets::GameAssets,
+    InRun,
+    assets::{Configs, GameAssets},
+    enemies::{boss::Boss, spawner::WaveAnnouncement},
     game::GameTimers,
-    health::PlayerHealth,
+    health::{EnemyHealth, PlayerHealth, HEART},
     player::Player,
-    weapons::{Weapon, WeaponChoice},
+    upgrades::Experience,
+    weapons::{HeldWeapon, Weapon},
     window::primary_window_exists,
 };
 
@@ -17,17 +21,74 @@ pub struct UiPlugin;
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app
+            .init_resource::<WeaponIcons>()
+            .init_resource::<Announcement>()
             .add_systems(Update, (
                 draw_health,
+                draw_experience,
                 draw_weapon,
+                draw_held_weapon,
                 draw_dice,
-                draw_reset_text,
                 draw_round_time,
-            ).run_if(in_state(AppState::InGame))
+                draw_boss_health,
+                draw_announcement,
+            ).run_if(in_state(InRun))
             .distributive_run_if(primary_window_exists));
     }
 }
 
+/// Weapon icon images by their path in the weapons config.
+#[derive(Default, Resource)]
+struct WeaponIcons(HashMap<String, Handle<Image>>);
+
+impl WeaponIcons {
+    fn get(&mut self, path: &str, asset_server: &AssetServer) -> Handle<Image> {
+        self.0.entry(path.to_string())
+            .or_insert_with(|| asset_server.load(path.to_string()))
+            .clone_weak()
+    }
+}
+
+const ANNOUNCEMENT_SECS: f32 = 3.0;
+
+/// The latest message from the wave schedule, shown until its timer runs out.
+#[derive(Default, Resource)]
+struct Announcement {
+    text: String,
+    timer: Timer,
+}
+
+fn draw_announcement(
+    mut egui_ctx: EguiContexts,
+    mut announcement: ResMut<Announcement>,
+    mut announcements: EventReader<WaveAnnouncement>,
+    time: Res<Time>,
+) {
+    use egui::{Align2, Color32, Frame, RichText, Window};
+
+    if let Some(latest) = announcements.read().last() {
+        announcement.text = latest.0.clone();
+        announcement.timer = Timer::from_seconds(ANNOUNCEMENT_SECS, TimerMode::Once);
+    }
+    announcement.timer.tick(time.delta());
+    if announcement.timer.finished() {
+        return;
+    }
+
+    let ctx = egui_ctx.ctx_mut();
+    let window = Window::new("Announcement")
+        .anchor(Align2::CENTER_CENTER, [0.0, -120.0])
+        .auto_sized()
+        .title_bar(false)
+        .frame(Frame::none());
+    window.show(ctx, |ui| {
+        let text = RichText::new(&announcement.text)
+            .color(Color32::YELLOW)
+            .size(36.0);
+        ui.label(text);
+    });
+}
+
 fn draw_round_time(
     mut egui_ctx: EguiContexts,
     game_timers: Res<GameTimers>,
@@ -49,36 +110,34 @@ fn draw_round_time(
     });
 }
 
-fn draw_reset_text(
+fn draw_boss_health(
     mut egui_ctx: EguiContexts,
-    game_timers: Res<GameTimers>,
+    boss_q: Query<(&Boss, &EnemyHealth)>,
 ) {
-    use egui::{Align2, Color32, Frame, RichText, Window};
+    use egui::{Align2, Color32, Frame, ProgressBar, RichText, Window};
 
-    let ctx = egui_ctx.ctx_mut();
-
-    if game_timers.reset_time.finished() {
-        let window = Window::new("ResetText")
-            .anchor(Align2::CENTER_CENTER, [0.0, -80.0])
-            .auto_sized()
-            .title_bar(false)
-            .frame(Frame::none());
-        window.show(ctx, |ui| {
-            ui.vertical_centered_justified(|ui| {
-                let text = RichText::new("YOU ROLLED... POORLY")
-                    .color(Color32::WHITE)
-                    .background_color(Color32::from_rgba_unmultiplied(0, 0, 0, 40))
-                    .size(60.0);
-                ui.label(text);
+    let Ok((boss, health)) = boss_q.get_single() else {
+        return;
+    };
 
-                let text = RichText::new("Press Space on keyboard or Start on gamepad to Try Again")
-                    .color(Color32::WHITE)
-                    .background_color(Color32::from_rgba_unmultiplied(0, 0, 0, 40))
-                    .size(30.0);
-                ui.label(text);
-            });
+    let ctx = egui_ctx.ctx_mut();
+    let window = Window::new("BossHealth")
+        .anchor(Align2::CENTER_TOP, [0.0, 80.0])
+        .auto_sized()
+        .title_bar(false)
+        .frame(Frame::none());
+    window.show(ctx, |ui| {
+        ui.vertical_centered(|ui| {
+            let text = RichText::new(boss.name())
+                .color(Color32::WHITE)
+                .size(24.0);
+            ui.label(text);
+            let bar = ProgressBar::new((health.current / health.max).clamp(0.0, 1.0))
+                .desired_width(400.0)
+                .fill(Color32::from_rgb(200, 40, 40));
+            ui.add(bar);
         });
-    }
+    });
 }
 
 fn draw_health(
@@ -104,15 +163,27 @@ fn draw_health(
             .frame(Frame::none());
         window.show(ctx, |ui| {
             ui.horizontal(|ui| {
+                // Health is in half hearts, so there can be one half heart between the whole and
+                // empty ones.
+                let whole = health.current / HEART;
+                let half = health.current % HEART;
+                let empty = health.max.div_ceil(HEART).saturating_sub(whole + half);
+
                 // Whole hearts.
                 let image = &assets.egui_images.whole_heart;
-                for _ in 0.. health.current {
+                for _ in 0..whole {
+                    ui.image(SizedTexture::new(image.id, (image.size * egui_scale).to_array()));
+                }
+
+                // Half heart.
+                let image = &assets.egui_images.half_heart;
+                for _ in 0..half {
                     ui.image(SizedTexture::new(image.id, (image.size * egui_scale).to_array()));
                 }
 
                 // Empty hearts.
                 let image = &assets.egui_images.empty_heart;
-                for _ in 0.. health.missing() {
+                for _ in 0..empty {
                     ui.image(SizedTexture::new(image.id, (image.size * egui_scale).to_array()));
                 }
             });
@@ -120,9 +191,46 @@ fn draw_health(
     }
 }
 
+fn draw_experience(
+    mut egui_ctx: EguiContexts,
+    configs: Configs,
+    experience_q: Query<&Experience, With<Player>>,
+) {
+    use egui::{Align2, Color32, Frame, ProgressBar, RichText, Window};
+
+    let Some(config) = configs.upgrades() else {
+        return;
+    };
+    let Ok(experience) = experience_q.get_single() else {
+        return;
+    };
+
+    let ctx = egui_ctx.ctx_mut();
+    let window = Window::new("Experience")
+        .anchor(Align2::LEFT_TOP, [20.0, 60.0])
+        .auto_sized()
+        .title_bar(false)
+        .frame(Frame::none());
+    window.show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            let text = RichText::new(format!("LV {}", experience.level))
+                .color(Color32::WHITE)
+                .size(24.0);
+            ui.label(text);
+
+            let needed = config.xp_to_next_level(experience.level).max(1);
+            let bar = ProgressBar::new((experience.xp as f32 / needed as f32).clamp(0.0, 1.0))
+                .desired_width(200.0)
+                .fill(Color32::from_rgb(80, 220, 255));
+            ui.add(bar);
+        });
+    });
+}
+
 fn draw_dice(
     mut egui_ctx: EguiContexts,
     assets: Res<GameAssets>,
+    configs: Configs,
     time: Res<Time>,
     weapon_q: Query<&Weapon>,
 ) {
@@ -131,6 +239,10 @@ fn draw_dice(
     let ctx = egui_ctx.ctx_mut();
     let egui_scale = 2.0;
 
+    let Some(weapons) = configs.weapons() else {
+        return;
+    };
+
     if let Ok(weapon) = weapon_q.get_single() {
         let window = Window::new("Dice")
             .anchor(Align2::LEFT_BOTTOM, [20.0, -20.0])
@@ -140,7 +252,8 @@ fn draw_dice(
         window.show(ctx, |ui| {
             ui.horizontal(|ui| {
                 if !weapon.reloading {
-                    let image = &assets.egui_images.dice[weapon.equipped as usize];
+                    let face = weapons.get(weapon.equipped).dice_face.clamp(1, assets.egui_images.dice.len());
+                    let image = &assets.egui_images.dice[face - 1];
                     ui.image(SizedTexture::new(image.id, (image.size * egui_scale).to_array()));
                 } else {
                     let just_millis = time.elapsed().as_millis() % 1000;
@@ -155,16 +268,35 @@ fn draw_dice(
 
 fn draw_weapon(
     mut egui_ctx: EguiContexts,
-    assets: Res<GameAssets>,
+    mut weapon_icons: ResMut<WeaponIcons>,
+    configs: Configs,
+    asset_server: Res<AssetServer>,
+    images: Res<Assets<Image>>,
     time: Res<Time>,
     weapon_q: Query<&Weapon>,
 ) {
     use egui::{Align2, Color32, Frame, RichText, Window};
 
-    let ctx = egui_ctx.ctx_mut();
     let egui_scale = 2.0;
 
+    let Some(weapons) = configs.weapons() else {
+        return;
+    };
+
     if let Ok(weapon) = weapon_q.get_single() {
+        let icon_def = if !weapon.reloading {
+            weapons.get(weapon.equipped)
+        } else {
+            // Cycle through all the weapon icons while re-rolling.
+            let just_millis = time.elapsed().as_millis() % 1000;
+            let bucket = (just_millis as f32 / 1000.0) * weapons.weapons.len() as f32;
+            &weapons.weapons[bucket as usize]
+        };
+        let icon = weapon_icons.get(&icon_def.icon, &asset_server);
+        let icon_size = images.get(&icon).map(|image| image.size().as_vec2());
+        let icon_id = egui_ctx.add_image(icon);
+
+        let ctx = egui_ctx.ctx_mut();
         let window = Window::new("Weapon")
             .anchor(Align2::LEFT_BOTTOM, [20.0, -60.0])
             .auto_sized()
@@ -172,46 +304,83 @@ fn draw_weapon(
             .frame(Frame::none());
         window.show(ctx, |ui| {
             ui.horizontal(|ui| {
-                if !weapon.reloading {
-                    let image = match weapon.equipped {
-                        WeaponChoice::Pistol => &assets.egui_images.weapons.pistol,
-                        WeaponChoice::RayGun => &assets.egui_images.weapons.ray_gun,
-                        WeaponChoice::Shotgun => &assets.egui_images.weapons.shotgun,
-                        WeaponChoice::Boomerang => &assets.egui_images.weapons.boomerang,
-                        WeaponChoice::Smg => &assets.egui_images.weapons.smg,
-                        WeaponChoice::GrenadeLauncher => &assets.egui_images.weapons.grenade_launcher,
-                    };
-                    ui.image(SizedTexture::new(image.id, (image.size * egui_scale).to_array()));
+                // Icons are loaded on demand, so only draw them once they're ready.
+                if let Some(size) = icon_size {
+                    ui.image(SizedTexture::new(icon_id, (size * egui_scale).to_array()));
 
                     ui.add_space(10.0);
+                }
 
-                    let ammo_text = RichText::new(format!("{} / {}", weapon.ammo, weapon.stats.max_ammo))
-                        .color(Color32::WHITE)
-                        .size(30.0);
-                    ui.label(ammo_text);
+                let text = if !weapon.reloading && weapon.bonus_damage > 0.0 {
+                    format!("{} / {}  +{:.0}% DMG", weapon.ammo, weapon.stats.max_ammo, weapon.bonus_damage * 100.0)
+                } else if !weapon.reloading {
+                    format!("{} / {}", weapon.ammo, weapon.stats.max_ammo)
                 } else {
-                    let just_millis = time.elapsed().as_millis() % 1000;
-                    let bucket = (just_millis as f32 / 1000.0) * 6.0;
-                    let image = match bucket as u32 {
-                        2 => &assets.egui_images.weapons.pistol,
-                        5 => &assets.egui_images.weapons.ray_gun,
-                        3 => &assets.egui_images.weapons.shotgun,
-                        4 => &assets.egui_images.weapons.boomerang,
-                        1 => &assets.egui_images.weapons.smg,
-                        0 => &assets.egui_images.weapons.grenade_launcher,
-                        _ => &assets.egui_images.weapons.pistol,
-                    };
+                    "Re-Rolling!".to_string()
+                };
+                let ammo_text = RichText::new(text)
+                    .color(Color32::WHITE)
+                    .size(30.0);
+                ui.label(ammo_text);
+            });
+        });
+    }
+}
 
-                    ui.image(SizedTexture::new(image.id, (image.size * egui_scale).to_array()));
+fn draw_held_weapon(
+    mut egui_ctx: EguiContexts,
+    mut weapon_icons: ResMut<WeaponIcons>,
+    configs: Configs,
+    asset_server: Res<AssetServer>,
+    images: Res<Assets<Image>>,
+    held_q: Query<&HeldWeapon>,
+) {
+    use egui::{Align2, Color32, Frame, RichText, Window};
 
-                    ui.add_space(10.0);
+    let egui_scale = 2.0;
 
-                    let ammo_text = RichText::new("Re-Rolling!")
-                        .color(Color32::WHITE)
-                        .size(30.0);
-                    ui.label(ammo_text);
+    let Some(weapons) = configs.weapons() else {
+        return;
+    };
+    let Ok(held) = held_q.get_single() else {
+        return;
+    };
+
+    let icon = held.weapon.as_ref().map(|weapon| {
+        let icon = weapon_icons.get(&weapons.get(weapon.equipped).icon, &asset_server);
+        let size = images.get(&icon).map(|image| image.size().as_vec2());
+        (egui_ctx.add_image(icon), size)
+    });
+
+    let ctx = egui_ctx.ctx_mut();
+    let window = Window::new("HeldWeapon")
+        .anchor(Align2::LEFT_BOTTOM, [20.0, -120.0])
+        .auto_sized()
+        .title_bar(false)
+        .frame(Frame::none());
+    window.show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            let label = RichText::new("Q/E")
+                .color(Color32::LIGHT_GRAY)
+                .size(20.0);
+            ui.label(label);
+            ui.add_space(10.0);
+
+            let text = match &held.weapon {
+                Some(weapon) => {
+                    // Drawn at half the size of the active weapon.
+                    if let Some((icon_id, Some(size))) = icon {
+                        ui.image(SizedTexture::new(icon_id, (size * egui_scale / 2.0).to_array()));
+                        ui.add_space(10.0);
+                    }
+                    format!("{} / {}", weapon.ammo, weapon.stats.max_ammo)
                 }
-            });
+                None => "Hold".to_string(),
+            };
+            let text = RichText::new(text)
+                .color(Color32::LIGHT_GRAY)
+                .size(20.0);
+            ui.label(text);
         });
-    }
+    });
 }
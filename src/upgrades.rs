@@ -0,0 +1,330 @@
+use bevy::prelude::*;
+use bevy::reflect::TypePath;
+use serde::Deserialize;
+
+use crate::{
+    AppState,
+    assets::Configs,
+    game::GameLogicSet,
+    health::{PlayerHealth, HEART},
+    pickups,
+    player::Player,
+    rng::GameRng,
+    weapons::{modifiers::ProjectileModifiers, Weapon, WeaponPool, WeaponStats, WeaponsConfig},
+};
+
+/// How many upgrades to pick from on each level up.
+const UPGRADE_OPTIONS: usize = 3;
+
+/// Experience, levelling up, and the upgrades picked along the way.
+pub struct UpgradesPlugin;
+
+impl Plugin for UpgradesPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<Experience>()
+            .register_type::<Upgrades>()
+            .add_event::<UpgradeChosen>()
+            .add_systems(FixedUpdate, check_level_up
+                .after(pickups::collect_pickups)
+                .in_set(GameLogicSet))
+            // Like pausing, but the music keeps playing.
+            .add_systems(OnEnter(AppState::LevelUp), pause_time)
+            .add_systems(OnExit(AppState::LevelUp), end_level_up)
+            .add_systems(Update, apply_chosen_upgrade.run_if(in_state(AppState::LevelUp)));
+    }
+}
+
+#[derive(Deserialize, Asset, TypePath)]
+pub struct UpgradesConfig {
+    /// Experience needed to reach level 2.
+    pub first_level_xp: u32,
+    /// How much more experience each level after that needs than the last.
+    pub level_xp_growth: u32,
+    pub upgrades: Vec<UpgradeDef>,
+}
+
+impl UpgradesConfig {
+    /// Experience needed to get from `level` to the next one.
+    pub fn xp_to_next_level(&self, level: u32) -> u32 {
+        self.first_level_xp + self.level_xp_growth * level.saturating_sub(1)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct UpgradeDef {
+    pub name: String,
+    pub effect: UpgradeEffect,
+    /// How many times it can be picked in a run.
+    pub max_stacks: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum UpgradeEffect {
+    /// Multiplies damage by 1 + this.
+    Damage(f32),
+    /// Multiplies shots per second by 1 + this.
+    FireRate(f32),
+    Ammo(u8),
+    /// Multiplies walk speed by 1 + this.
+    MoveSpeed(f32),
+    /// Multiplies spread by 1 - this.
+    Accuracy(f32),
+    Projectiles(u8),
+    /// In hearts.
+    MaxHealth(u8),
+    /// Makes the named weapon this many times as likely to come up when re-rolling.
+    LoadDice(String, f32),
+    /// The named weapon never comes up when re-rolling.
+    BanWeapon(String),
+    Pierce(u32),
+    Ricochet(u32),
+    /// In degrees per second.
+    Homing(f32),
+    Chain(u32),
+    Split(u32),
+}
+
+impl UpgradeEffect {
+    pub fn description(&self) -> String {
+        match self {
+            Self::Damage(amount) => format!("+{:.0}% damage", amount * 100.0),
+            Self::FireRate(amount) => format!("+{:.0}% fire rate", amount * 100.0),
+            Self::Ammo(amount) => format!("+{} ammo", amount),
+            Self::MoveSpeed(amount) => format!("+{:.0}% move speed", amount * 100.0),
+            Self::Accuracy(amount) => format!("-{:.0}% spread", amount * 100.0),
+            Self::Projectiles(amount) => format!("+{} projectiles per shot", amount),
+            Self::MaxHealth(amount) => format!("+{} max hearts", amount),
+            Self::LoadDice(weapon, factor) => format!("{} comes up {}x as often", weapon, factor),
+            Self::BanWeapon(weapon) => format!("{} never comes up", weapon),
+            Self::Pierce(amount) => format!("Shots pierce {} more enemies", amount),
+            Self::Ricochet(amount) => format!("Shots bounce to {} more enemies", amount),
+            Self::Homing(amount) => format!("Shots home in, turning {:.0} degrees per second", amount),
+            Self::Chain(amount) => format!("Hits chain to {} more enemies", amount),
+            Self::Split(amount) => format!("Shots split into {} more pieces", amount),
+        }
+    }
+}
+
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct Experience {
+    /// Towards the next level.
+    pub xp: u32,
+    pub level: u32,
+}
+
+impl Default for Experience {
+    fn default() -> Self {
+        Self {
+            xp: 0,
+            level: 1,
+        }
+    }
+}
+
+/// Bonuses from every upgrade picked this run. They're applied on top of the equipped weapon's
+/// stats, so they carry over when it's re-rolled.
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct Upgrades {
+    /// How many times each upgrade in the config has been picked, by index.
+    stacks: Vec<u32>,
+    pub damage_multiplier: f32,
+    pub fire_rate_multiplier: f32,
+    pub extra_ammo: u8,
+    pub move_speed_multiplier: f32,
+    pub spread_multiplier: f32,
+    pub extra_projectiles: u8,
+    /// Added to the modifiers of every projectile fired.
+    pub projectile_modifiers: ProjectileModifiers,
+}
+
+impl Default for Upgrades {
+    fn default() -> Self {
+        Self {
+            stacks: Vec::new(),
+            damage_multiplier: 1.0,
+            fire_rate_multiplier: 1.0,
+            extra_ammo: 0,
+            move_speed_multiplier: 1.0,
+            spread_multiplier: 1.0,
+            extra_projectiles: 0,
+            projectile_modifiers: default(),
+        }
+    }
+}
+
+impl Upgrades {
+    pub fn stacks(&self, index: usize) -> u32 {
+        self.stacks.get(index).copied().unwrap_or(0)
+    }
+
+    fn add(&mut self, index: usize, effect: &UpgradeEffect) {
+        if self.stacks.len() <= index {
+            self.stacks.resize(index + 1, 0);
+        }
+        self.stacks[index] += 1;
+
+        match *effect {
+            UpgradeEffect::Damage(amount) => self.damage_multiplier *= 1.0 + amount,
+            UpgradeEffect::FireRate(amount) => self.fire_rate_multiplier *= 1.0 + amount,
+            UpgradeEffect::Ammo(amount) => self.extra_ammo = self.extra_ammo.saturating_add(amount),
+            UpgradeEffect::MoveSpeed(amount) => self.move_speed_multiplier *= 1.0 + amount,
+            UpgradeEffect::Accuracy(amount) => self.spread_multiplier *= (1.0 - amount).max(0.0),
+            UpgradeEffect::Projectiles(amount) => self.extra_projectiles = self.extra_projectiles.saturating_add(amount),
+            UpgradeEffect::Pierce(amount) => self.projectile_modifiers.pierce += amount,
+            UpgradeEffect::Ricochet(amount) => self.projectile_modifiers.ricochet += amount,
+            UpgradeEffect::Homing(amount) => self.projectile_modifiers.homing += amount,
+            UpgradeEffect::Chain(amount) => self.projectile_modifiers.chain += amount,
+            UpgradeEffect::Split(amount) => self.projectile_modifiers.split += amount,
+            // These go straight to the player's health or the weapon pool.
+            UpgradeEffect::MaxHealth(_) | UpgradeEffect::LoadDice(..) | UpgradeEffect::BanWeapon(_) => {}
+        }
+    }
+
+    /// A weapon's stats from the config with these upgrades applied.
+    pub fn weapon_stats(&self, base: &WeaponStats) -> WeaponStats {
+        WeaponStats {
+            max_ammo: base.max_ammo.saturating_add(self.extra_ammo),
+            fire_rate: base.fire_rate / self.fire_rate_multiplier,
+            projectiles_per_shot: base.projectiles_per_shot.saturating_add(self.extra_projectiles),
+            spread: base.spread * self.spread_multiplier,
+            crit_chance: base.crit_chance,
+            crit_multiplier: base.crit_multiplier,
+        }
+    }
+
+    /// Upgrades that haven't been picked as many times as they can be yet. Bans that the weapon
+    /// pool would refuse are left out, so picking one never wastes a level.
+    fn available(&self, config: &UpgradesConfig, weapons: &WeaponsConfig, weapon_pool: &WeaponPool) -> Vec<usize> {
+        config.upgrades.iter()
+            .enumerate()
+            .filter(|(index, def)| self.stacks(*index) < def.max_stacks)
+            .filter(|(_, def)| match &def.effect {
+                UpgradeEffect::BanWeapon(name) => weapons.find(name)
+                    .is_some_and(|choice| weapon_pool.can_ban(choice, weapons)),
+                _ => true,
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// The upgrades offered on the level up screen, as indices into the config.
+#[derive(Resource)]
+pub struct LevelUpChoices {
+    pub options: Vec<usize>,
+}
+
+/// Sent with the index into `LevelUpChoices::options` of the upgrade the player picked.
+#[derive(Event, Clone, Copy)]
+pub struct UpgradeChosen(pub usize);
+
+fn check_level_up(
+    mut commands: Commands,
+    configs: Configs,
+    weapon_pool: Res<WeaponPool>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut rng: ResMut<GameRng>,
+    choices: Option<Res<LevelUpChoices>>,
+    mut player_q: Query<(&mut Experience, &Upgrades, &PlayerHealth), With<Player>>,
+) {
+    // Several fixed steps can run in the frame before the level up screen shows.
+    if choices.is_some() {
+        return;
+    }
+    let (Some(config), Some(weapons)) = (configs.upgrades(), configs.weapons()) else {
+        return;
+    };
+    let Ok((mut experience, upgrades, health)) = player_q.get_single_mut() else {
+        return;
+    };
+    if health.current == 0 {
+        return;
+    }
+
+    // Only one level at a time, the rest get picked up once we're back in the game.
+    let needed = config.xp_to_next_level(experience.level);
+    if experience.xp < needed {
+        return;
+    }
+    experience.xp -= needed;
+    experience.level += 1;
+    info!("Reached level {}!", experience.level);
+
+    let mut options = upgrades.available(config, weapons, &weapon_pool);
+    if options.is_empty() {
+        return;
+    }
+    rng.upgrades.shuffle(&mut options);
+    options.truncate(UPGRADE_OPTIONS);
+
+    commands.insert_resource(LevelUpChoices { options });
+    next_state.set(AppState::LevelUp);
+}
+
+fn apply_chosen_upgrade(
+    configs: Configs,
+    choices: Option<Res<LevelUpChoices>>,
+    mut weapon_pool: ResMut<WeaponPool>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut chosen: EventReader<UpgradeChosen>,
+    mut player_q: Query<(&mut Upgrades, &mut Weapon, &mut PlayerHealth), With<Player>>,
+) {
+    let Some(&UpgradeChosen(option)) = chosen.read().last() else {
+        return;
+    };
+    let Some(choices) = choices else {
+        return;
+    };
+    let (Some(config), Some(weapons)) = (configs.upgrades(), configs.weapons()) else {
+        return;
+    };
+    let Some((index, def)) = choices.options.get(option)
+        .and_then(|&index| Some((index, config.upgrades.get(index)?))) else {
+        warn!("No upgrade option {}!", option);
+        return;
+    };
+    let Ok((mut upgrades, mut weapon, mut health)) = player_q.get_single_mut() else {
+        return;
+    };
+
+    info!("Picked upgrade {}", def.name);
+    upgrades.add(index, &def.effect);
+    match &def.effect {
+        UpgradeEffect::MaxHealth(hearts) => {
+            health.raise_max(hearts.saturating_mul(HEART));
+        }
+        UpgradeEffect::LoadDice(name, factor) => match weapons.find(name) {
+            Some(choice) => weapon_pool.load(choice, *factor),
+            None => warn!("Can't load the dice towards {}, there's no weapon with that name!", name),
+        },
+        UpgradeEffect::BanWeapon(name) => match weapons.find(name) {
+            Some(choice) => {
+                if !weapon_pool.ban(choice, weapons) {
+                    warn!("Can't ban {}, there'd be nothing left to re-roll into!", name);
+                }
+            }
+            None => warn!("Can't ban {}, there's no weapon with that name!", name),
+        },
+        _ => {}
+    }
+    weapon.refresh_stats(weapons, &upgrades);
+
+    next_state.set(AppState::InGame);
+}
+
+fn pause_time(
+    mut time: ResMut<Time<Virtual>>,
+) {
+    time.pause();
+}
+
+fn end_level_up(
+    mut commands: Commands,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    commands.remove_resource::<LevelUpChoices>();
+    time.unpause();
+}
@@ -1,21 +1,39 @@
+use std::collections::{HashMap, HashSet};
 use std::ops::RangeInclusive;
 
 use bevy::prelude::*;
 use bevy::math::Mat2;
+use bevy::reflect::TypePath;
 use bevy_kira_audio::prelude::*;
 use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
 
 use crate::{
     AppState,
     animation::{self, Animation, AnimationState},
-    assets::{AudioAssets, AudioConfig, GameAssets},
+    assets::{Configs, GameAssets, ProjectileIndices, Sfx},
     combat::*,
-    game::{Facing, Lifetime},
+    game::{self, Facing, GameLogicSet, Lifetime, RunEntity},
     health::PlayerHealth,
+    interpolation::Interpolated,
     physics::groups,
-    player::{update_player_aim, Player, PlayerInput},
+    player::{Player, PlayerInput},
+    rng::{self, GameRng},
+    stats::RunStats,
+    status::StatusEffectSpec,
+    upgrades::Upgrades,
 };
 
+pub mod modifiers;
+
+use modifiers::{EnemyQuery, Pierce, ProjectileModifiers, Ricochet, SplitQuery};
+
+/// How long re-rolling takes once a weapon runs out of ammo.
+const REROLL_SECS: f32 = 2.0;
+/// Extra damage for the new weapon when re-rolling early with a full magazine. Less ammo left
+/// gives less of a bonus.
+const EARLY_REROLL_MAX_BONUS: f32 = 0.5;
+
 pub struct WeaponPlugin;
 
 impl Plugin for WeaponPlugin {
@@ -23,84 +41,144 @@ impl Plugin for WeaponPlugin {
         app
             .register_type::<WeaponChoice>()
             .register_type::<Weapon>()
-            .add_systems(Update, (
-                fire_weapon.after(update_player_aim),
+            .register_type::<HeldWeapon>()
+            .init_resource::<WeaponPool>()
+            .add_systems(FixedUpdate, (
+                swap_held_weapon.before(fire_weapon),
+                reroll_early.after(swap_held_weapon).before(fire_weapon),
+                fire_weapon,
+                finish_reroll.after(fire_weapon),
+                modifiers::home_projectiles.before(update_projectile_movement),
                 update_projectile_movement,
+                modifiers::chain_lightning.after(check_hits),
+                modifiers::split_expiring_projectiles.before(game::update_lifetimes),
                 boomerang_movement,
                 despawn_projectile_on_hit.after(check_hits),
                 explode_grenade.after(check_hits),
-            ).run_if(in_state(AppState::InGame)));
-    }
-}
-
-#[derive(Clone, Copy, PartialEq, Eq, Default, Reflect)]
-pub enum WeaponChoice {
-    #[default]
-    Pistol,
-    RayGun,
-    Shotgun,
-    Boomerang,
-    Smg,
-    GrenadeLauncher,
-}
-
-impl WeaponChoice {
-    pub fn random() -> Self {
-        let choice = fastrand::u8(0..6);
-        match choice {
-            0 => Self::Pistol,
-            1 => Self::RayGun,
-            2 => Self::Shotgun,
-            3 => Self::Boomerang,
-            4 => Self::Smg,
-            5 => Self::GrenadeLauncher,
-            _ => Self::Pistol,
-        }
+            ).in_set(GameLogicSet))
+            .add_systems(Update, apply_weapons_config_changes.run_if(in_state(AppState::InGame)));
     }
+}
 
-    pub fn get_weapon_stats(&self) -> WeaponStats {
-        match self {
-            Self::Pistol => WeaponStats {
-                max_ammo: 16,
-                fire_rate: 0.3,
-                projectiles_per_shot: 1,
-                spread: 0.0,
-            },
-            Self::RayGun => WeaponStats {
-                max_ammo: 12,
-                fire_rate: 0.5,
-                projectiles_per_shot: 1,
-                spread: 0.0,
-            },
-            Self::Shotgun => WeaponStats {
-                max_ammo: 8,
-                fire_rate: 0.8,
-                projectiles_per_shot: 10,
-                spread: 75.0,
-            },
-            Self::Boomerang => WeaponStats {
-                max_ammo: 8,
-                fire_rate: 1.0,
-                projectiles_per_shot: 1,
-                spread: 0.0,
-            },
-            Self::Smg => WeaponStats {
-                max_ammo: 64,
-                fire_rate: 0.1,
-                projectiles_per_shot: 1,
-                spread: 30.0,
-            },
-            Self::GrenadeLauncher => WeaponStats {
-                max_ammo: 5,
-                fire_rate: 1.0,
-                projectiles_per_shot: 1,
-                spread: 0.0,
-            },
+/// Index of a weapon in the weapons config. The default is the first weapon, which the config
+/// always has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default, Reflect)]
+pub struct WeaponChoice(pub usize);
+
+/// How likely each weapon is to come up when re-rolling, for the current run. Starts out with the
+/// weights from the weapons config, and upgrades can load the dice towards a weapon or ban one.
+#[derive(Default, Resource)]
+pub struct WeaponPool {
+    /// Multiplies the weapon's weight from the config.
+    loaded: HashMap<WeaponChoice, f32>,
+    banned: HashSet<WeaponChoice>,
+}
+
+impl WeaponPool {
+    pub fn weight(&self, choice: WeaponChoice, weapons: &WeaponsConfig) -> f32 {
+        if self.banned.contains(&choice) || !weapons.contains(choice) {
+            return 0.0;
         }
+        weapons.get(choice).weight * self.loaded.get(&choice).copied().unwrap_or(1.0)
+    }
+
+    pub fn is_banned(&self, choice: WeaponChoice) -> bool {
+        self.banned.contains(&choice)
+    }
+
+    /// Makes a weapon `factor` times as likely to come up.
+    pub fn load(&mut self, choice: WeaponChoice, factor: f32) {
+        *self.loaded.entry(choice).or_insert(1.0) *= factor;
+    }
+
+    /// Whether `ban` would work. Banning a weapon has to leave something different to re-roll
+    /// into, and can't ban the same weapon twice.
+    pub fn can_ban(&self, choice: WeaponChoice, weapons: &WeaponsConfig) -> bool {
+        let allowed = (0..weapons.weapons.len())
+            .map(WeaponChoice)
+            .filter(|&other| other != choice && !self.banned.contains(&other))
+            .count();
+        weapons.contains(choice) && !self.banned.contains(&choice) && allowed >= 2
     }
+
+    /// Stops a weapon from coming up again. Returns false without banning it if `can_ban` doesn't
+    /// allow it.
+    pub fn ban(&mut self, choice: WeaponChoice, weapons: &WeaponsConfig) -> bool {
+        self.can_ban(choice, weapons) && self.banned.insert(choice)
+    }
+
+    /// Picks a weapon other than `current`. Only falls back to `current` if every other weapon is
+    /// banned.
+    pub fn roll(&self, current: WeaponChoice, weapons: &WeaponsConfig, rng: &mut fastrand::Rng) -> WeaponChoice {
+        let candidates: Vec<(WeaponChoice, f32)> = (0..weapons.weapons.len())
+            .map(WeaponChoice)
+            .filter(|&choice| choice != current && !self.banned.contains(&choice))
+            .map(|choice| (choice, self.weight(choice, weapons)))
+            .collect();
+
+        rng::pick_weighted(&candidates, rng)
+            // If none of them have any weight, they're all equally likely.
+            .or_else(|| (!candidates.is_empty()).then(|| candidates[rng.usize(0..candidates.len())].0))
+            .unwrap_or(if weapons.contains(current) { current } else { WeaponChoice::default() })
+    }
+}
+
+#[derive(Deserialize, Asset, TypePath)]
+pub struct WeaponsConfig {
+    /// Never empty. A config without weapons fails to load, and a hot reload keeps the previous
+    /// config instead.
+    #[serde(deserialize_with = "non_empty")]
+    pub weapons: Vec<WeaponDef>,
+}
+
+fn non_empty<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<WeaponDef>, D::Error> {
+    let weapons = Vec::<WeaponDef>::deserialize(deserializer)?;
+    if weapons.is_empty() {
+        return Err(serde::de::Error::custom("the weapons config needs at least one weapon"));
+    }
+    Ok(weapons)
+}
+
+impl WeaponsConfig {
+    /// Falls back to the first weapon if the choice isn't in the config anymore, which can happen
+    /// for a frame after a hot reload removes weapons.
+    pub fn get(&self, choice: WeaponChoice) -> &WeaponDef {
+        self.weapons.get(choice.0)
+            .unwrap_or(&self.weapons[0])
+    }
+
+    pub fn contains(&self, choice: WeaponChoice) -> bool {
+        choice.0 < self.weapons.len()
+    }
+
+    pub fn find(&self, name: &str) -> Option<WeaponChoice> {
+        self.weapons.iter()
+            .position(|weapon| weapon.name == name)
+            .map(WeaponChoice)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct WeaponDef {
+    pub name: String,
+    /// Path to the image shown in the HUD.
+    pub icon: String,
+    /// Which face of the dice (1-6) is shown in the HUD.
+    pub dice_face: usize,
+    /// Key of the sound played when firing.
+    pub sound: String,
+    /// How likely it is to come up when re-rolling, relative to the other weapons.
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+    pub stats: WeaponStats,
+    pub projectile: ProjectileDef,
 }
 
-#[derive(Default, Reflect)]
+fn default_weight() -> f32 {
+    1.0
+}
+
+#[derive(Clone, Default, Debug, PartialEq, Deserialize, Reflect)]
 pub struct WeaponStats {
     pub max_ammo: u8,
     /// Time between each shot.
@@ -108,6 +186,86 @@ pub struct WeaponStats {
     pub projectiles_per_shot: u8,
     // Angle in degrees of cone of spread.
     pub spread: f32,
+    /// From 0 to 1.
+    #[serde(default)]
+    pub crit_chance: f32,
+    /// What to multiply damage by on a crit.
+    #[serde(default = "default_crit_multiplier")]
+    pub crit_multiplier: f32,
+}
+
+fn default_crit_multiplier() -> f32 {
+    2.0
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ProjectileDef {
+    pub damage: f32,
+    #[serde(default)]
+    pub knockback: Option<KnockbackSpec>,
+    pub speed: ProjectileSpeed,
+    pub hit_box_size: Vec2,
+    #[serde(default)]
+    pub die_on_hit: bool,
+    /// Burning, slowing, and so on.
+    #[serde(default)]
+    pub statuses: Vec<StatusEffectSpec>,
+    /// How long until it can hit the same enemy again. If not set, it hits each enemy once.
+    #[serde(default)]
+    pub rehit_interval: Option<f32>,
+    #[serde(default)]
+    pub modifiers: ProjectileModifiers,
+    pub behavior: ProjectileBehavior,
+}
+
+#[derive(Clone, Deserialize)]
+pub enum ProjectileBehavior {
+    /// Flies in a straight line until its lifetime runs out.
+    Straight {
+        sprite: ProjectileSprite,
+        lifetime: f32,
+    },
+    /// Flies outward, then returns to the player.
+    Boomerang {
+        return_time: f32,
+    },
+    /// Explodes on hit or once its fuse runs out.
+    Grenade {
+        sprite: ProjectileSprite,
+        fuse: f32,
+        explosion: ExplosionDef,
+    },
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub enum ProjectileSprite {
+    Orb,
+    Bullet,
+    Laser,
+    Sparkle,
+    Grenade,
+}
+
+impl ProjectileSprite {
+    fn index(&self, indices: &ProjectileIndices) -> usize {
+        match self {
+            Self::Orb => indices.orb,
+            Self::Bullet => indices.bullet,
+            Self::Laser => indices.laser,
+            Self::Sparkle => indices.sparkle,
+            Self::Grenade => indices.grenade,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ExplosionDef {
+    pub damage: f32,
+    pub radius: f32,
+    /// Size of the explosion sprite.
+    pub size: f32,
+    pub lifetime: f32,
+    pub sound: String,
 }
 
 #[derive(Default, Component, Reflect)]
@@ -118,28 +276,87 @@ pub struct Weapon {
     pub ammo: u8,
     /// How long until next shot is allowed.
     pub cooldown: f32,
+    /// Extra damage from re-rolling early into this weapon, e.g. 0.2 for 20% more.
+    pub bonus_damage: f32,
+    /// Extra damage the next weapon gets once this re-roll finishes.
+    pub next_bonus_damage: f32,
 }
 
 impl Weapon {
-    pub fn new(choice: WeaponChoice) -> Self {
-        let stats = choice.get_weapon_stats();
+    pub fn new(choice: WeaponChoice, weapons: &WeaponsConfig, upgrades: &Upgrades) -> Self {
+        let stats = upgrades.weapon_stats(&weapons.get(choice).stats);
         Self {
             equipped: choice,
             reloading: false,
             ammo: stats.max_ammo,
             stats,
             cooldown: 0.0,
+            bonus_damage: 0.0,
+            next_bonus_damage: 0.0,
         }
     }
+
+    pub fn damage_multiplier(&self, upgrades: &Upgrades) -> f32 {
+        upgrades.damage_multiplier * (1.0 + self.bonus_damage)
+    }
+
+    /// Recalculate stats after the config or upgrades change, keeping the weapon equipped. Extra
+    /// max ammo gets added to the current ammo too.
+    pub fn refresh_stats(&mut self, weapons: &WeaponsConfig, upgrades: &Upgrades) {
+        let stats = upgrades.weapon_stats(&weapons.get(self.equipped).stats);
+        if !self.reloading {
+            self.ammo = self.ammo.saturating_add(stats.max_ammo.saturating_sub(self.stats.max_ammo));
+        }
+        self.ammo = self.ammo.min(stats.max_ammo);
+        self.stats = stats;
+    }
+}
+
+/// A second weapon the player can hold on to and swap with the active one.
+#[derive(Default, Component, Reflect)]
+pub struct HeldWeapon {
+    pub weapon: Option<Weapon>,
+    /// Whether swap was pressed last timestep, so holding it down only swaps once.
+    swap_pressed: bool,
 }
 
-enum ProjectileSpeed {
+/// Lets the player throw away their remaining ammo and start re-rolling without waiting for the
+/// magazine to run out. It takes as long as any other re-roll, but the more ammo they give up, the
+/// more damage the new weapon does.
+#[derive(Default, Component)]
+pub struct EarlyReroll {
+    /// Whether re-roll was pressed last timestep, so holding it down only re-rolls once.
+    pressed: bool,
+}
+
+#[derive(Clone, Deserialize)]
+pub enum ProjectileSpeed {
     Single(f32),
     RandomRange(RangeInclusive<f32>),
 }
 
+impl ProjectileSpeed {
+    fn pick(&self, rng: &mut fastrand::Rng) -> f32 {
+        match self {
+            Self::Single(s) => *s,
+            Self::RandomRange(range) => {
+                let range_delta = range.end() - range.start();
+                range.start() + (range_delta * rng.f32())
+            }
+        }
+    }
+}
+
 #[derive(Component)]
-struct DieOnHit;
+pub struct DieOnHit;
+
+/// The shot a projectile or explosion came from, so its hits can be credited to it.
+#[derive(Clone, Copy, Component)]
+pub struct FiredBy {
+    pub weapon: WeaponChoice,
+    /// Numbers shots in a run. All projectiles fired at once share the same shot.
+    pub shot: u32,
+}
 
 #[derive(Component)]
 struct ProjectileMovement {
@@ -155,18 +372,20 @@ impl ProjectileMovement {
 }
 
 #[derive(Bundle)]
-struct ProjectileBundle {
+pub struct ProjectileBundle {
     movement: ProjectileMovement,
     facing: Facing,
     // TODO: sprite
     sprite: SpriteBundle,
     atlas: TextureAtlas,
+    interpolated: Interpolated,
+    run_entity: RunEntity,
 
     body: RigidBody,
 }
 
 impl ProjectileBundle {
-    fn new(speed: f32, pos: Vec2, dir: Vec2, texture: Handle<Image>, atlas: Handle<TextureAtlasLayout>, sprite_index: usize) -> Self {
+    pub fn new(speed: f32, pos: Vec2, dir: Vec2, texture: Handle<Image>, atlas: Handle<TextureAtlasLayout>, sprite_index: usize) -> Self {
         Self {
             movement: ProjectileMovement::new(speed * dir),
             facing: Facing { dir },
@@ -180,15 +399,23 @@ impl ProjectileBundle {
                 layout: atlas,
                 index: sprite_index,
             },
+            interpolated: default(),
+            run_entity: RunEntity,
             body: RigidBody::KinematicPositionBased,
         }
     }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.sprite.sprite.color = color;
+        self
+    }
 }
 
 #[derive(Component)]
 pub struct Grenade {
     explode_timer: f32,
     exploded: bool,
+    explosion: ExplosionDef,
 }
 
 #[derive(Bundle)]
@@ -197,46 +424,55 @@ struct GrenadeBundle {
     movement: ProjectileMovement,
     sprite: SpriteBundle,
     atlas: TextureAtlas,
+    interpolated: Interpolated,
+    run_entity: RunEntity,
 
     body: RigidBody,
     velocity: Velocity,
 }
 
 impl GrenadeBundle {
-    fn new(pos: Vec2, dir: Vec2, texture: Handle<Image>, atlas: Handle<TextureAtlasLayout>, sprite_index: usize) -> Self {
-        let speed = 150.0;
+    fn new(speed: f32, pos: Vec2, dir: Vec2, fuse: f32, explosion: ExplosionDef, assets: &GameAssets, sprite_index: usize) -> Self {
         let velocity = Velocity {
             linvel: dir * speed,
             ..default()
         };
         Self {
             grenade: Grenade {
-                explode_timer: 0.6,
+                explode_timer: fuse,
                 exploded: false,
+                explosion,
             },
-            movement: ProjectileMovement::new(dir * speed),// { speed, die_on_hit: false },
+            movement: ProjectileMovement::new(dir * speed),
             sprite: SpriteBundle {
                 sprite: Sprite {
                     custom_size: Some(Vec2::new(12.0, 12.0)),
                     ..default()
                 },
-                texture,
+                texture: assets.projectiles.clone(),
                 transform: Transform::from_translation(pos.extend(15.0))
                     .with_rotation(Quat::from_rotation_z(Vec2::Y.angle_between(dir))),
                 ..default()
             },
             atlas: TextureAtlas {
-                layout: atlas,
+                layout: assets.projectile_atlas.clone(),
                 index: sprite_index,
             },
+            interpolated: default(),
+            run_entity: RunEntity,
             body: RigidBody::KinematicPositionBased,
             velocity,
         }
     }
 }
 
+/// Marks grenade explosions, so the camera can shake when they go off.
+#[derive(Component)]
+pub struct Explosion;
+
 #[derive(Bundle)]
 struct ExplosionBundle {
+    explosion: Explosion,
     sprite: SpriteBundle,
     atlas: TextureAtlas,
     name: Name,
@@ -248,14 +484,16 @@ struct ExplosionBundle {
     sensor: Sensor,
     active_events: ActiveEvents,
     lifetime: Lifetime,
+    run_entity: RunEntity,
 }
 
 impl ExplosionBundle {
-    fn new(pos: Vec2, texture: Handle<Image>, atlas: Handle<TextureAtlasLayout>, sprite_index: usize) -> Self {
+    fn new(pos: Vec2, explosion: &ExplosionDef, texture: Handle<Image>, atlas: Handle<TextureAtlasLayout>, sprite_index: usize) -> Self {
         Self {
+            explosion: Explosion,
             sprite: SpriteBundle {
                 sprite: Sprite {
-                    custom_size: Some(Vec2::new(96.0, 96.0)),
+                    custom_size: Some(Vec2::splat(explosion.size)),
                     ..default()
                 },
                 texture,
@@ -267,13 +505,14 @@ impl ExplosionBundle {
                 index: sprite_index,
             },
             name: Name::new("Grenade Explosion"),
-            hit_box: HitSpec::new(40.0),
+            hit_box: HitSpec::new(explosion.damage),
             body: RigidBody::KinematicPositionBased,
-            collider: Collider::ball(50.0),
+            collider: Collider::ball(explosion.radius),
             layers: CollisionGroups::new(groups::HIT, groups::HURT),
             sensor: Sensor,
             active_events: ActiveEvents::COLLISION_EVENTS,
-            lifetime: Lifetime::new(0.8),
+            lifetime: Lifetime::new(explosion.lifetime),
+            run_entity: RunEntity,
         }
     }
 }
@@ -282,38 +521,39 @@ fn explode_grenade(
     mut commands: Commands,
     time: Res<Time>,
     assets: Res<GameAssets>,
-    sounds: Res<AudioAssets>,
-    audio: Res<Audio>,
+    sfx: Sfx,
     mut hits: EventReader<HitEvent>,
-    mut grenade_q: Query<(Entity, &mut Grenade, &GlobalTransform)>,
+    mut grenade_q: Query<(Entity, &mut Grenade, &GlobalTransform, Option<&FiredBy>)>,
 ) {
     let dt = time.delta_seconds();
 
-    let mut explode_grenade = |entity, grenade: &mut Grenade, transform: &GlobalTransform| {
+    let mut explode_grenade = |entity, grenade: &mut Grenade, transform: &GlobalTransform, fired_by: Option<&FiredBy>| {
         grenade.exploded = true;
         commands.entity(entity).despawn();
 
-        let explosion = ExplosionBundle::new(transform.translation().truncate(), assets.effects.clone(), assets.effects_atlas.clone(), 3);
-        commands.spawn(explosion);
+        let explosion = ExplosionBundle::new(transform.translation().truncate(), &grenade.explosion, assets.effects.clone(), assets.effects_atlas.clone(), 3);
+        let mut builder = commands.spawn(explosion);
+        if let Some(fired_by) = fired_by {
+            builder.insert(*fired_by);
+        }
 
-        // TODO: Pipe in volume from config.
-        audio.play(sounds.grenade_explosion.clone()).with_volume(1.0);
+        sfx.play(&grenade.explosion.sound);
     };
 
     // Explode grenades either on hit or after time expires.
     for hit in hits.read() {
-        if let Ok((entity, mut grenade, transform)) = grenade_q.get_mut(hit.attacker) {
+        if let Ok((entity, mut grenade, transform, fired_by)) = grenade_q.get_mut(hit.attacker) {
             if !grenade.exploded {
-                explode_grenade(entity, &mut *grenade, transform);
+                explode_grenade(entity, &mut grenade, transform, fired_by);
             }
         }
     }
 
-    for (entity, mut grenade, transform) in grenade_q.iter_mut() {
+    for (entity, mut grenade, transform, fired_by) in grenade_q.iter_mut() {
         grenade.explode_timer = (grenade.explode_timer - dt).max(0.0);
 
         if !grenade.exploded && grenade.explode_timer == 0.0 {
-            explode_grenade(entity, &mut *grenade, transform);
+            explode_grenade(entity, &mut grenade, transform, fired_by);
         }
     }
 }
@@ -334,33 +574,36 @@ struct BoomerangBundle {
     anim: Handle<Animation>,
     anim_state: AnimationState,
     play: animation::Play,
+    interpolated: Interpolated,
+    run_entity: RunEntity,
 
     body: RigidBody,
 }
 
 impl BoomerangBundle {
-    fn new(pos: Vec2, dir: Vec2, texture: Handle<Image>, atlas: Handle<TextureAtlasLayout>, anim: Handle<Animation>, audio_instance: Handle<AudioInstance>) -> Self {
-        let speed = 150.0;
+    fn new(speed: f32, pos: Vec2, dir: Vec2, return_time: f32, assets: &GameAssets, audio_instance: Handle<AudioInstance>) -> Self {
         Self {
             boomerang: Boomerang {
                 outgoing_velocity: dir * speed,
-                return_time: 0.8,
+                return_time,
                 audio_instance,
             },
             facing: Facing { dir },
             sprite: SpriteBundle {
-                texture,
+                texture: assets.boomerang_projectile.clone(),
                 transform: Transform::from_translation(pos.extend(15.0))
                     .with_rotation(Quat::from_rotation_z(Vec2::Y.angle_between(dir))),
                 ..default()
             },
             atlas: TextureAtlas {
-                layout: atlas,
+                layout: assets.boomerang_atlas.clone(),
                 ..default()
             },
-            anim,
+            anim: assets.boomerang_anim.clone(),
             anim_state: AnimationState::default(),
             play: animation::Play,
+            interpolated: default(),
+            run_entity: RunEntity,
             body: RigidBody::KinematicPositionBased,
         }
     }
@@ -397,134 +640,91 @@ fn boomerang_movement(
     }
 }
 
+fn swap_held_weapon(
+    configs: Configs,
+    weapon_pool: Res<WeaponPool>,
+    mut rng: ResMut<GameRng>,
+    mut stats: ResMut<RunStats>,
+    mut q: Query<(&mut Weapon, &mut HeldWeapon, &Upgrades, &PlayerInput, &PlayerHealth)>,
+) {
+    let Some(weapons) = configs.weapons() else {
+        return;
+    };
+
+    for (mut weapon, mut held, upgrades, input, health) in q.iter_mut() {
+        let pressed = input.next_weapon || input.prev_weapon;
+        let just_pressed = pressed && !held.swap_pressed;
+        held.swap_pressed = pressed;
+
+        // An empty weapon is about to be re-rolled, so there's nothing worth holding on to.
+        if !just_pressed || weapon.reloading || health.current == 0 {
+            continue;
+        }
+
+        let next = match held.weapon.take() {
+            Some(mut next) => {
+                // Upgrades may have been picked while it was held.
+                next.refresh_stats(weapons, upgrades);
+                next
+            }
+            None => {
+                // Nothing held yet, so hold on to this one and roll a new one.
+                let choice = weapon_pool.roll(weapon.equipped, weapons, &mut rng.weapons);
+                stats.rerolls += 1;
+                Weapon::new(choice, weapons, upgrades)
+            }
+        };
+        held.weapon = Some(std::mem::replace(&mut *weapon, next));
+    }
+}
+
+/// Starts the same re-roll as running out of ammo. `finish_reroll` finishes it.
+fn reroll_early(
+    mut q: Query<(&mut Weapon, &mut EarlyReroll, &PlayerInput, &PlayerHealth)>,
+) {
+    for (mut weapon, mut early_reroll, input, health) in q.iter_mut() {
+        let just_pressed = input.reroll && !early_reroll.pressed;
+        early_reroll.pressed = input.reroll;
+        if !just_pressed || weapon.reloading || health.current == 0 {
+            continue;
+        }
+
+        let ammo_left = weapon.ammo as f32 / weapon.stats.max_ammo.max(1) as f32;
+        weapon.next_bonus_damage = EARLY_REROLL_MAX_BONUS * ammo_left.min(1.0);
+        weapon.ammo = 0;
+        weapon.reloading = true;
+        weapon.cooldown = REROLL_SECS;
+        debug!("Re-rolling early with {:.0}% ammo left", ammo_left * 100.0);
+    }
+}
+
 fn fire_weapon(
     mut commands: Commands,
     time: Res<Time>,
-    assets: Res<GameAssets>,
-    sounds: Res<AudioAssets>,
-    audio_configs: Res<Assets<AudioConfig>>,
-    audio: Res<Audio>,
-    mut q: Query<(&mut Weapon, &PlayerInput, &Transform, &Facing, &PlayerHealth)>,
+    configs: Configs,
+    sfx: Sfx,
+    mut rng: ResMut<GameRng>,
+    mut stats: ResMut<RunStats>,
+    mut q: Query<(&mut Weapon, &Upgrades, &PlayerInput, &Transform, &Facing, &PlayerHealth)>,
 ) {
-    let sfx_volumes = &audio_configs.get(&sounds.config)
-        .expect("Audio config asset not loaded proplery!")
-        .sfx_volumes;
+    let assets = &configs.assets;
+    let weapons = configs.weapons()
+        .expect("Weapons config asset not loaded properly!");
 
     let dt = time.delta_seconds();
-    for (mut weapon, input, transform, facing, health) in q.iter_mut() {
+    for (mut weapon, upgrades, input, transform, facing, health) in q.iter_mut() {
         // Update weapon cooldown.
         weapon.cooldown = (weapon.cooldown - dt).max(0.0);
 
-        if weapon.reloading && weapon.cooldown == 0.0 {
-            // Pick new weapon!
-            weapon.equipped = {
-                let mut choice = WeaponChoice::random();
-                while choice == weapon.equipped {
-                    choice = WeaponChoice::random();
-                }
-                choice
-            };
-            weapon.stats = weapon.equipped.get_weapon_stats();
-            weapon.ammo = weapon.stats.max_ammo;
-
-            weapon.reloading = false;
-
-            // Don't shoot this frame.
-            continue;
-        }
-
         // Check if we want to shoot and can shoot.
         if !input.shoot || weapon.cooldown > 0.0 || weapon.ammo == 0 || health.current == 0 {
             continue;
         }
 
         // Get projectile properties.
-        let (damage, knockback, sprite_index, speed, lifetime, hit_box_size, die_on_hit, name) = match weapon.equipped {
-            WeaponChoice::Pistol => (
-                4.0,
-                KnockbackSpec {
-                    direction: KnockbackDirection::AttackerFacing,
-                    frames: 6,
-                    distance: 10.0,
-                },
-                assets.projectile_indices.bullet,
-                ProjectileSpeed::Single(200.0),
-                2.0,
-                Vec2::new(2.0, 4.0),
-                true,
-                "Projectile: Pistol",
-            ),
-            WeaponChoice::RayGun => (
-                5.0,
-                KnockbackSpec {
-                    direction: KnockbackDirection::AttackerFacing,
-                    frames: 6,
-                    distance: 8.0,
-                },
-                assets.projectile_indices.laser,
-                ProjectileSpeed::Single(200.0),
-                5.0,
-                Vec2::new(2.0, 4.0),
-                false,
-                "Projectile: RayGun",
-            ),
-            WeaponChoice::Shotgun => (
-                8.0,
-                KnockbackSpec {
-                    direction: KnockbackDirection::AttackerFacing,
-                    frames: 6,
-                    distance: 20.0,
-                },
-                assets.projectile_indices.bullet,
-                ProjectileSpeed::RandomRange(100.0..=200.0),
-                0.5,
-                Vec2::new(2.0, 4.0),
-                true,
-                "Projectile: Shotgun",
-            ),
-            WeaponChoice::Boomerang => (
-                3.0,
-                KnockbackSpec {
-                    direction: KnockbackDirection::AwayFromAttacker,
-                    frames: 12,
-                    distance: 14.0,
-                },
-                assets.projectile_indices.bullet,
-                ProjectileSpeed::Single(200.0),
-                20.0,
-                Vec2::new(6.0, 6.0),
-                false,
-                "Projectile: Boomerang",
-            ),
-            WeaponChoice::Smg => (
-                2.0,
-                KnockbackSpec {
-                    direction: KnockbackDirection::AttackerFacing,
-                    frames: 6,
-                    distance: 6.0,
-                },
-                assets.projectile_indices.bullet,
-                ProjectileSpeed::Single(200.0),
-                2.0,
-                Vec2::new(2.0, 4.0),
-                true,
-                "Projectile: SMG",
-            ),
-            WeaponChoice::GrenadeLauncher => (
-                20.0,
-                KnockbackSpec {
-                    direction: KnockbackDirection::AttackerFacing,
-                    frames: 6,
-                    distance: 40.0,
-                },
-                assets.projectile_indices.grenade,
-                ProjectileSpeed::Single(200.0),
-                10.0,
-                Vec2::new(4.0, 4.0),
-                true,
-                "Projectile: Grenade Launcher",
-            ),
-        };
+        let weapon_def = weapons.get(weapon.equipped);
+        let projectile = &weapon_def.projectile;
+        let name = format!("Projectile: {}", weapon_def.name);
 
         // Determine fire direction. Either aim direction or facing if aim is zero.
         let aim_dir = if input.aim != Vec2::ZERO {
@@ -533,91 +733,78 @@ fn fire_weapon(
             facing.dir
         };
 
+        let fired_by = FiredBy {
+            weapon: weapon.equipped,
+            shot: stats.record_shot(),
+        };
+
         // Spawn projectiles.
         for _ in 0..weapon.stats.projectiles_per_shot {
             // Compute common data.
             let fire_dir = if weapon.stats.spread > 0.0 {
                 // Rotate dir based on spread.
-                let spread_angle = (fastrand::f32() * weapon.stats.spread) - (weapon.stats.spread / 2.0);
+                let spread_angle = (rng.weapons.f32() * weapon.stats.spread) - (weapon.stats.spread / 2.0);
                 Mat2::from_angle(spread_angle.to_radians()) * aim_dir
             } else {
                 aim_dir
             };
             let pos = transform.translation.truncate() + (fire_dir * 10.0);
-            let hit_box = HitSpec::new(damage)
-                .with_knockback(knockback.clone());
-            let collider_shape = Collider::cuboid(hit_box_size.x, hit_box_size.y);
+            let speed = projectile.speed.pick(&mut rng.weapons);
+            let damage = projectile.damage * weapon.damage_multiplier(upgrades);
+            let mut hit_box = HitSpec::new(damage);
+            if let Some(knockback) = &projectile.knockback {
+                hit_box = hit_box.with_knockback(knockback.clone());
+            }
+            hit_box = hit_box
+                .with_statuses(projectile.statuses.iter().cloned())
+                .with_crit(weapon.stats.crit_chance, weapon.stats.crit_multiplier);
+            if let Some(interval) = projectile.rehit_interval {
+                hit_box = hit_box.with_rehit_interval(interval);
+            }
+            let collider_shape = Collider::cuboid(projectile.hit_box_size.x, projectile.hit_box_size.y);
             let collision_layers = CollisionGroups::new(groups::HIT, groups::HURT);
 
-            if weapon.equipped == WeaponChoice::GrenadeLauncher {
-                let bundle = GrenadeBundle::new(pos, fire_dir, assets.projectiles.clone(), assets.projectile_atlas.clone(), sprite_index);
-                let mut builder = commands.spawn((
-                    bundle,
-                    Name::new(name),
-                    hit_box,
-                    collider_shape,
-                    collision_layers,
-                    Sensor,
-                    ActiveEvents::COLLISION_EVENTS,
-                ));
-                if die_on_hit {
-                    builder.insert(DieOnHit);
+            let mut builder = match &projectile.behavior {
+                ProjectileBehavior::Grenade { sprite, fuse, explosion } => {
+                    sfx.play(&weapon_def.sound);
+
+                    let explosion = ExplosionDef {
+                        damage: explosion.damage * weapon.damage_multiplier(upgrades),
+                        ..explosion.clone()
+                    };
+                    let sprite_index = sprite.index(&assets.projectile_indices);
+                    let bundle = GrenadeBundle::new(speed, pos, fire_dir, *fuse, explosion, assets, sprite_index);
+                    commands.spawn(bundle)
                 }
+                ProjectileBehavior::Boomerang { return_time } => {
+                    let audio_instance = sfx.play(&weapon_def.sound)
+                        .map(|mut command| command.looped().handle())
+                        .unwrap_or_default();
 
-                audio.play(sounds.grenade.clone()).with_volume(sfx_volumes.grenade as f64);
-            } else if weapon.equipped == WeaponChoice::Boomerang {
-                let audio_instance = audio.play(sounds.boomerang.clone())
-                    .looped()
-                    .with_volume(sfx_volumes.boomerang as f64)
-                    .handle();
-
-                let bundle = BoomerangBundle::new(pos, fire_dir, assets.boomerang_projectile.clone(), assets.boomerang_atlas.clone(), assets.boomerang_anim.clone(), audio_instance);
-                let mut builder = commands.spawn((
-                    bundle,
-                    Name::new(name),
-                    hit_box,
-                    collider_shape,
-                    collision_layers,
-                    Sensor,
-                    ActiveEvents::COLLISION_EVENTS,
-                ));
-                if die_on_hit {
-                    builder.insert(DieOnHit);
-                }
-            } else {
-                let speed = match &speed {
-                    ProjectileSpeed::Single(s) => *s,
-                    ProjectileSpeed::RandomRange(range) => {
-                        let range_delta = range.end() - range.start();
-                        range.start() + (range_delta * fastrand::f32())
-                    }
-                };
-                let projectile_bundle = ProjectileBundle::new(speed, pos, fire_dir, assets.projectiles.clone(), assets.projectile_atlas.clone(), sprite_index);
-                let mut builder = commands.spawn((
-                    projectile_bundle,
-                    Name::new(name),
-                    Lifetime::new(lifetime),
-                    hit_box,
-                    collider_shape,
-                    collision_layers,
-                    Sensor,
-                    ActiveEvents::COLLISION_EVENTS,
-                ));
-                if die_on_hit {
-                    builder.insert(DieOnHit);
+                    let bundle = BoomerangBundle::new(speed, pos, fire_dir, *return_time, assets, audio_instance);
+                    commands.spawn(bundle)
                 }
+                ProjectileBehavior::Straight { sprite, lifetime } => {
+                    sfx.play(&weapon_def.sound);
 
-                let (sound, volume) = match weapon.equipped {
-                    WeaponChoice::Pistol => (&sounds.pistol, sfx_volumes.pistol),
-                    WeaponChoice::RayGun => (&sounds.raygun, sfx_volumes.raygun),
-                    WeaponChoice::Shotgun => (&sounds.shotgun, sfx_volumes.shotgun),
-                    WeaponChoice::Boomerang => (&sounds.boomerang, sfx_volumes.boomerang),
-                    WeaponChoice::Smg => (&sounds.smg, sfx_volumes.smg),
-                    WeaponChoice::GrenadeLauncher => (&sounds.grenade, sfx_volumes.grenade),
-                };
-
-                audio.play(sound.clone()).with_volume(volume as f64);
+                    let sprite_index = sprite.index(&assets.projectile_indices);
+                    let bundle = ProjectileBundle::new(speed, pos, fire_dir, assets.projectiles.clone(), assets.projectile_atlas.clone(), sprite_index);
+                    commands.spawn((bundle, Lifetime::new(*lifetime)))
+                }
+            };
+            builder.insert((
+                Name::new(name.clone()),
+                hit_box,
+                collider_shape,
+                collision_layers,
+                Sensor,
+                ActiveEvents::COLLISION_EVENTS,
+                fired_by,
+            ));
+            if projectile.die_on_hit {
+                builder.insert(DieOnHit);
             }
+            projectile.modifiers.stack(&upgrades.projectile_modifiers).insert(&mut builder);
         }
 
         // Spend ammo and start cooldown.
@@ -626,11 +813,69 @@ fn fire_weapon(
             weapon.stats.fire_rate
         } else {
             weapon.reloading = true;
-            2.0
+            REROLL_SECS
         };
     }
 }
 
+/// Swaps an empty weapon for a new one once it's done re-rolling. Runs after `fire_weapon`, so the
+/// new weapon doesn't shoot until the next frame.
+fn finish_reroll(
+    configs: Configs,
+    weapon_pool: Res<WeaponPool>,
+    mut rng: ResMut<GameRng>,
+    mut stats: ResMut<RunStats>,
+    mut q: Query<(&mut Weapon, &Upgrades)>,
+) {
+    let weapons = configs.weapons()
+        .expect("Weapons config asset not loaded properly!");
+
+    for (mut weapon, upgrades) in q.iter_mut() {
+        if weapon.reloading && weapon.cooldown == 0.0 {
+            // Pick new weapon!
+            let choice = weapon_pool.roll(weapon.equipped, weapons, &mut rng.weapons);
+            let bonus_damage = weapon.next_bonus_damage;
+            *weapon = Weapon::new(choice, weapons, upgrades);
+            weapon.bonus_damage = bonus_damage;
+            stats.rerolls += 1;
+        }
+    }
+}
+
+/// Apply weapon config changes to already equipped weapons when it's hot reloaded.
+fn apply_weapons_config_changes(
+    configs: Configs,
+    mut config_events: EventReader<AssetEvent<WeaponsConfig>>,
+    mut weapon_q: Query<(&mut Weapon, &mut HeldWeapon, &Upgrades)>,
+) {
+    let modified = config_events.read()
+        .any(|event| event.is_modified(&configs.assets.weapons));
+    if !modified {
+        return;
+    }
+
+    let Some(weapons) = configs.weapons() else {
+        return;
+    };
+
+    info!("Weapons config changed, updating equipped weapons.");
+    for (mut weapon, mut held, upgrades) in weapon_q.iter_mut() {
+        refresh_reloaded_weapon(&mut weapon, weapons, upgrades);
+        if let Some(held_weapon) = held.weapon.as_mut() {
+            refresh_reloaded_weapon(held_weapon, weapons, upgrades);
+        }
+    }
+}
+
+/// Weapons removed from the config are swapped for the default one.
+fn refresh_reloaded_weapon(weapon: &mut Weapon, weapons: &WeaponsConfig, upgrades: &Upgrades) {
+    if weapons.contains(weapon.equipped) {
+        weapon.refresh_stats(weapons, upgrades);
+    } else {
+        *weapon = Weapon::new(WeaponChoice::default(), weapons, upgrades);
+    }
+}
+
 fn update_projectile_movement(
     time: Res<Time>,
     mut q: Query<(&mut Transform, &ProjectileMovement)>,
@@ -641,14 +886,38 @@ fn update_projectile_movement(
     }
 }
 
+/// What a projectile needs to carry on after a hit.
+type SurvivingProjectile = (&'static mut ProjectileMovement, &'static mut Transform, &'static mut Facing, Option<&'static mut Pierce>, Option<&'static mut Ricochet>);
+
 fn despawn_projectile_on_hit(
     mut commands: Commands,
+    assets: Res<GameAssets>,
     mut hits: EventReader<HitEvent>,
     die_on_hit_q: Query<(), With<DieOnHit>>,
+    mut projectile_q: Query<SurvivingProjectile>,
+    split_q: SplitQuery,
+    enemy_q: EnemyQuery,
 ) {
+    // A projectile can hit several things in the same frame, but only dies once.
+    let mut despawned = HashSet::new();
     for hit in hits.read() {
-        if die_on_hit_q.contains(hit.attacker) {
-            commands.entity(hit.attacker).despawn();
+        if !die_on_hit_q.contains(hit.attacker) || despawned.contains(&hit.attacker) {
+            continue;
         }
+
+        if let Ok((mut movement, mut transform, mut facing, pierce, ricochet)) = projectile_q.get_mut(hit.attacker) {
+            if modifiers::survive_hit(hit, &mut movement, &mut transform, &mut facing, pierce, ricochet, &enemy_q) {
+                continue;
+            }
+
+            // Split around the middle of what it hit.
+            let pos = enemy_q.get(hit.defender)
+                .map(|(_, defender_transform)| defender_transform.translation().truncate())
+                .unwrap_or(transform.translation.truncate());
+            modifiers::split_projectile(hit.attacker, pos, facing.dir, movement.velocity.length(), &split_q, &mut commands, &assets);
+        }
+
+        commands.entity(hit.attacker).despawn();
+        despawned.insert(hit.attacker);
     }
 }
@@ -0,0 +1,302 @@
+use bevy::prelude::*;
+use bevy::ecs::system::EntityCommands;
+use bevy::math::Mat2;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    assets::GameAssets,
+    combat::{HitEvent, HitSpec},
+    enemies::{Death, Enemy},
+    game::{Facing, Lifetime, RunEntity},
+    physics::groups,
+};
+
+use super::{DieOnHit, FiredBy, ProjectileBundle, ProjectileMovement};
+
+/// How far a ricochet looks for another enemy to bounce to.
+const RICOCHET_RANGE: f32 = 100.0;
+/// How far a homing projectile looks for an enemy to turn towards.
+const HOMING_RANGE: f32 = 120.0;
+/// How far chain lightning can jump.
+const CHAIN_RANGE: f32 = 60.0;
+/// Each jump deals this much of the damage of the hit it jumped from.
+const CHAIN_DAMAGE_FRACTION: f32 = 0.5;
+const CHAIN_ARC_LIFETIME: f32 = 0.1;
+const CHAIN_ARC_RADIUS: f32 = 2.0;
+const CHAIN_COLOR: Color = Color::srgb(1.0, 1.0, 0.4);
+/// Each piece deals this much of the damage of the projectile that split.
+const SPLIT_DAMAGE_FRACTION: f32 = 0.5;
+const SPLIT_LIFETIME: f32 = 0.4;
+/// How far from where the projectile died the pieces start, so they don't hit whatever it hit
+/// again.
+const SPLIT_OFFSET: f32 = 16.0;
+
+/// Extra behavior for projectiles, on top of how they move. Modifiers from the weapon and from
+/// upgrades stack.
+#[derive(Clone, Default, Debug, PartialEq, Deserialize, Reflect)]
+#[serde(default)]
+pub struct ProjectileModifiers {
+    /// How many enemies a projectile that dies on hit can pass through first.
+    pub pierce: u32,
+    /// How many times a projectile that dies on hit bounces to the nearest other enemy instead.
+    pub ricochet: u32,
+    /// How fast it turns towards the nearest enemy, in degrees per second.
+    pub homing: f32,
+    /// How many times each hit jumps on to another enemy nearby.
+    pub chain: u32,
+    /// How many smaller projectiles it breaks into when it dies.
+    pub split: u32,
+}
+
+impl ProjectileModifiers {
+    pub fn stack(&self, other: &ProjectileModifiers) -> Self {
+        Self {
+            pierce: self.pierce + other.pierce,
+            ricochet: self.ricochet + other.ricochet,
+            homing: self.homing + other.homing,
+            chain: self.chain + other.chain,
+            split: self.split + other.split,
+        }
+    }
+
+    pub fn insert(&self, builder: &mut EntityCommands) {
+        if self.pierce > 0 {
+            builder.insert(Pierce { remaining: self.pierce });
+        }
+        if self.ricochet > 0 {
+            builder.insert(Ricochet { remaining: self.ricochet });
+        }
+        if self.homing > 0.0 {
+            builder.insert(Homing { turn_rate: self.homing.to_radians() });
+        }
+        if self.chain > 0 {
+            builder.insert(ChainLightning { remaining: self.chain, already_hit: Vec::new() });
+        }
+        if self.split > 0 {
+            builder.insert(Split { count: self.split });
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct Pierce {
+    pub remaining: u32,
+}
+
+#[derive(Component)]
+pub struct Ricochet {
+    pub remaining: u32,
+}
+
+#[derive(Component)]
+pub struct Homing {
+    /// In radians per second.
+    turn_rate: f32,
+}
+
+/// Hits jump on to the nearest enemy that hasn't been hit by this chain yet.
+#[derive(Component)]
+pub struct ChainLightning {
+    remaining: u32,
+    already_hit: Vec<Entity>,
+}
+
+#[derive(Component)]
+pub struct Split {
+    count: u32,
+}
+
+/// A living enemy's entity and position.
+pub(super) type EnemyQuery<'w, 's> = Query<'w, 's, (Entity, &'static GlobalTransform), (With<Enemy>, Without<Death>)>;
+
+fn nearest_enemy(pos: Vec2, range: f32, exclude: &[Entity], enemy_q: &EnemyQuery<'_, '_>) -> Option<(Entity, Vec2)> {
+    enemy_q.iter()
+        .filter(|(entity, _)| !exclude.contains(entity))
+        .map(|(entity, transform)| (entity, transform.translation().truncate()))
+        .filter(|(_, enemy_pos)| enemy_pos.distance(pos) <= range)
+        .min_by(|(_, a), (_, b)| a.distance_squared(pos).total_cmp(&b.distance_squared(pos)))
+}
+
+/// Points a projectile in a new direction, keeping its speed.
+fn turn_projectile(dir: Vec2, movement: &mut ProjectileMovement, transform: &mut Transform, facing: &mut Facing) {
+    movement.velocity = dir * movement.velocity.length();
+    transform.rotation = Quat::from_rotation_z(Vec2::Y.angle_between(dir));
+    facing.dir = dir;
+}
+
+/// Called when a projectile that dies on hit hits something. Returns true if a modifier saved
+/// it, by bouncing it on to another enemy or letting it pierce through.
+pub(super) fn survive_hit(
+    hit: &HitEvent,
+    movement: &mut ProjectileMovement,
+    transform: &mut Transform,
+    facing: &mut Facing,
+    pierce: Option<Mut<'_, Pierce>>,
+    ricochet: Option<Mut<'_, Ricochet>>,
+    enemy_q: &EnemyQuery<'_, '_>,
+) -> bool {
+    if let Some(mut ricochet) = ricochet.filter(|ricochet| ricochet.remaining > 0) {
+        let pos = transform.translation.truncate();
+        if let Some((_, target)) = nearest_enemy(pos, RICOCHET_RANGE, &[hit.defender], enemy_q) {
+            ricochet.remaining -= 1;
+            turn_projectile((target - pos).normalize_or(facing.dir), movement, transform, facing);
+            return true;
+        }
+    }
+
+    if let Some(mut pierce) = pierce.filter(|pierce| pierce.remaining > 0) {
+        pierce.remaining -= 1;
+        return true;
+    }
+
+    false
+}
+
+/// What pieces of a split projectile copy from it.
+pub(super) type SplitQuery<'w, 's> = Query<'w, 's, (
+    &'static Split,
+    &'static HitSpec,
+    &'static Collider,
+    &'static TextureAtlas,
+    &'static Sprite,
+    Option<&'static FiredBy>,
+)>;
+
+/// Breaks a dying projectile into smaller pieces spread evenly around it, if it splits.
+pub(super) fn split_projectile(
+    entity: Entity,
+    pos: Vec2,
+    dir: Vec2,
+    speed: f32,
+    split_q: &SplitQuery<'_, '_>,
+    commands: &mut Commands,
+    assets: &GameAssets,
+) {
+    let Ok((split, hit_spec, collider, atlas, sprite, fired_by)) = split_q.get(entity) else {
+        return;
+    };
+
+    for i in 0..split.count {
+        let angle = i as f32 / split.count as f32 * std::f32::consts::TAU;
+        let piece_dir = Mat2::from_angle(angle) * dir;
+        let piece_pos = pos + piece_dir * SPLIT_OFFSET;
+        let bundle = ProjectileBundle::new(speed, piece_pos, piece_dir, assets.projectiles.clone(), assets.projectile_atlas.clone(), atlas.index)
+            .with_color(sprite.color);
+        let mut builder = commands.spawn((
+            bundle,
+            Name::new("Projectile: Split"),
+            hit_spec.scaled(SPLIT_DAMAGE_FRACTION),
+            collider.clone(),
+            CollisionGroups::new(groups::HIT, groups::HURT),
+            Sensor,
+            ActiveEvents::COLLISION_EVENTS,
+            DieOnHit,
+            Lifetime::new(SPLIT_LIFETIME),
+        ));
+        if let Some(fired_by) = fired_by {
+            builder.insert(*fired_by);
+        }
+    }
+}
+
+/// Projectiles that run out of time split too, not just ones that die on hit.
+pub(super) fn split_expiring_projectiles(
+    mut commands: Commands,
+    time: Res<Time>,
+    assets: Res<GameAssets>,
+    projectile_q: Query<(Entity, &Lifetime, &ProjectileMovement, &Transform), With<Split>>,
+    split_q: SplitQuery,
+) {
+    let dt = time.delta_seconds();
+    for (entity, lifetime, movement, transform) in projectile_q.iter() {
+        if lifetime.remaining > dt {
+            continue;
+        }
+
+        let speed = movement.velocity.length();
+        let dir = movement.velocity.normalize_or(Vec2::X);
+        split_projectile(entity, transform.translation.truncate(), dir, speed, &split_q, &mut commands, &assets);
+    }
+}
+
+pub(super) fn home_projectiles(
+    time: Res<Time>,
+    mut projectile_q: Query<(&Homing, &mut ProjectileMovement, &mut Transform, &mut Facing)>,
+    enemy_q: EnemyQuery,
+) {
+    let dt = time.delta_seconds();
+    for (homing, mut movement, mut transform, mut facing) in projectile_q.iter_mut() {
+        let pos = transform.translation.truncate();
+        let Some((_, target)) = nearest_enemy(pos, HOMING_RANGE, &[], &enemy_q) else {
+            continue;
+        };
+        let Some(dir) = movement.velocity.try_normalize() else {
+            continue;
+        };
+
+        let angle_to_target = dir.angle_between(target - pos);
+        let turn = angle_to_target.clamp(-homing.turn_rate * dt, homing.turn_rate * dt);
+        turn_projectile(Mat2::from_angle(turn) * dir, &mut movement, &mut transform, &mut facing);
+    }
+}
+
+/// Jumps hits from projectiles with chain lightning on to the next enemy, with a short-lived arc
+/// that hits it like any other hit box. The arc carries the rest of the chain with it.
+pub(super) fn chain_lightning(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    mut hits: EventReader<HitEvent>,
+    chain_q: Query<(&ChainLightning, Option<&FiredBy>)>,
+    enemy_q: EnemyQuery,
+) {
+    for hit in hits.read() {
+        let Ok((chain, fired_by)) = chain_q.get(hit.attacker) else {
+            continue;
+        };
+        let Ok((_, defender_transform)) = enemy_q.get(hit.defender) else {
+            continue;
+        };
+
+        let mut already_hit = chain.already_hit.clone();
+        already_hit.push(hit.defender);
+        let from = defender_transform.translation().truncate();
+        let Some((_, target)) = nearest_enemy(from, CHAIN_RANGE, &already_hit, &enemy_q) else {
+            continue;
+        };
+
+        let mut builder = commands.spawn((
+            Name::new("ChainLightning"),
+            SpriteBundle {
+                sprite: Sprite {
+                    color: CHAIN_COLOR,
+                    ..default()
+                },
+                texture: assets.projectiles.clone(),
+                transform: Transform::from_translation(target.extend(15.0)),
+                ..default()
+            },
+            TextureAtlas {
+                layout: assets.projectile_atlas.clone(),
+                index: assets.projectile_indices.sparkle,
+            },
+            HitSpec::new(hit.damage * CHAIN_DAMAGE_FRACTION),
+            RigidBody::KinematicPositionBased,
+            Collider::ball(CHAIN_ARC_RADIUS),
+            CollisionGroups::new(groups::HIT, groups::HURT),
+            Sensor,
+            ActiveEvents::COLLISION_EVENTS,
+            Lifetime::new(CHAIN_ARC_LIFETIME),
+            RunEntity,
+        ));
+        if chain.remaining > 1 {
+            builder.insert(ChainLightning {
+                remaining: chain.remaining - 1,
+                already_hit,
+            });
+        }
+        if let Some(fired_by) = fired_by {
+            builder.insert(*fired_by);
+        }
+    }
+}
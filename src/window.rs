@@ -7,7 +7,7 @@ use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
 use bevy::window::PrimaryWindow;
 use serde::{Deserialize, Serialize};
 
-use crate::DEFAULT_SCALE;
+use crate::{AppState, DEFAULT_SCALE};
 
 pub const WINDOW_TITLE: &str = "Re-Rolling!";
 const WINDOW_STATE_FILENAME: &str = "window_state.ron";
@@ -76,7 +76,8 @@ impl Plugin for WindowPlugin {
                 .add_systems(PostUpdate, log_fps_in_window_title.after(update_window_state));
         }
         if crate::ALLOW_EXIT {
-            app.add_systems(Update, close_on_esc);
+            // Escape pauses the game, so only quit from the main menu.
+            app.add_systems(Update, close_on_esc.run_if(in_state(AppState::MainMenu)));
         }
     }
 }
@@ -0,0 +1,839 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::{ActiveEvents, CollisionGroups, Group, RigidBody};
+use re_rolling::{
+    AppState,
+    combat::{HitBoxBundle, HitSpec},
+    damage_numbers::DamageNumber,
+    assets::GameAssets,
+    enemies::{
+        boss::{Arena, Boss},
+        formation::SpawnWarning,
+        spawner::{EnemyCount, Spawner},
+        AiPhasing, AiRanged, Enemy, EnemyKind,
+    },
+    game::{CameraView, GameTimers},
+    game_feel::{CameraShake, HitFlash, HitStop},
+    headless::Simulation,
+    health::{EnemyHealth, PlayerHealth, HEART},
+    pickups::{Pickup, PickupKind},
+    player::{Player, PlayerInput, PostHitInvulnerability},
+    settings::Settings,
+    stats::RunStats,
+    status::{StatusEffectSpec, StatusEffects, StatusKind},
+    upgrades::{Experience, LevelUpChoices, UpgradeEffect, Upgrades, UpgradesConfig},
+    weapons::{modifiers::ProjectileModifiers, HeldWeapon, Weapon, WeaponChoice, WeaponPool, WeaponsConfig},
+};
+
+const SEED: u64 = 1234;
+
+#[test]
+fn pistol_shot_damages_rat() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let rat = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(40.0, 0.0));
+
+    // The player starts with the pistol. Fire a single shot at the rat.
+    sim.set_input(PlayerInput {
+        aim: Vec2::X,
+        shoot: true,
+        ..default()
+    });
+    sim.step();
+    sim.set_input(PlayerInput::default());
+    sim.run_for(0.5);
+
+    let health = sim.get::<EnemyHealth>(rat).unwrap();
+    assert_eq!(health.current, health.max - 4.0);
+}
+
+#[test]
+fn run_stats_track_pistol_kill() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    sim.spawn_enemy(EnemyKind::Rat, Vec2::new(60.0, 0.0));
+
+    // Rats have 10 HP, so it takes three pistol shots.
+    sim.set_input(PlayerInput {
+        aim: Vec2::X,
+        shoot: true,
+        ..default()
+    });
+    sim.run_for(1.0);
+
+    let stats = sim.app.world().resource::<RunStats>();
+    assert_eq!(stats.kills.get(&EnemyKind::Rat), Some(&1));
+    assert_eq!(stats.damage_dealt.get(&WeaponChoice(0)), Some(&12.0));
+    assert_eq!(stats.shots_hit(), 3);
+    assert!(stats.shots_fired >= 3);
+}
+
+#[test]
+fn spawner_fills_up_to_max_enemies() {
+    let mut sim = Simulation::new(SEED);
+    sim.run_for(30.0);
+    let count = sim.app.world().resource::<EnemyCount>().0;
+    assert!((25..=30).contains(&count), "{} enemies after 30 s", count);
+
+    // The spawner stops at 50 enemies for the first minute.
+    sim.run_for(29.0);
+    assert_eq!(sim.app.world().resource::<EnemyCount>().0, 50);
+}
+
+#[test]
+fn same_seed_plays_out_the_same() {
+    let enemy_positions = |seed| {
+        let mut sim = Simulation::new(seed);
+        sim.set_input(PlayerInput {
+            movement: Vec2::new(1.0, 0.5).normalize(),
+            aim: Vec2::Y,
+            shoot: true,
+            ..default()
+        });
+        sim.run_for(10.0);
+        let world = sim.app.world_mut();
+        world.query_filtered::<&Transform, With<Enemy>>()
+            .iter(world)
+            .map(|transform| transform.translation.truncate())
+            .collect::<Vec<_>>()
+    };
+
+    let positions = enemy_positions(SEED);
+    assert!(!positions.is_empty());
+    assert_eq!(positions, enemy_positions(SEED));
+}
+
+#[test]
+fn restarting_rebuilds_a_clean_world() {
+    let mut sim = Simulation::new(SEED);
+    let first_player = sim.player();
+    sim.run_for(20.0);
+    sim.app.world_mut().resource_mut::<CameraShake>().trauma = 1.0;
+    sim.app.world_mut().resource_mut::<HitStop>().remaining = 1.0;
+
+    sim.set_state(AppState::GameOver);
+    sim.set_state(AppState::InGame);
+
+    let world = sim.app.world_mut();
+    assert_eq!(world.resource::<CameraShake>().trauma, 0.0);
+    assert_eq!(world.resource::<HitStop>().remaining, 0.0);
+    assert_eq!(world.query::<&Player>().iter(world).count(), 1);
+    assert_eq!(world.query::<&Enemy>().iter(world).count(), 0);
+    assert_eq!(world.query::<&Spawner>().single(world).max_enemies, 50);
+    assert_eq!(world.resource::<EnemyCount>().0, 0);
+    assert_ne!(sim.player(), first_player);
+}
+
+#[test]
+fn hollow_phases_out_of_collision() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let hollow = sim.spawn_enemy(EnemyKind::Hollow, Vec2::new(200.0, 0.0));
+
+    sim.run_for(2.5);
+    assert!(!sim.get::<AiPhasing>(hollow).unwrap().phased());
+
+    // While phased out, none of its colliders touch anything.
+    sim.run_for(1.0);
+    assert!(sim.get::<AiPhasing>(hollow).unwrap().phased());
+    let children = sim.get::<Children>(hollow).unwrap().to_vec();
+    for child in children {
+        assert_eq!(sim.get::<CollisionGroups>(child).unwrap().memberships, Group::NONE);
+    }
+
+    sim.run_for(1.5);
+    assert!(!sim.get::<AiPhasing>(hollow).unwrap().phased());
+}
+
+#[test]
+fn cultist_shoots_the_player() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let cultist = sim.spawn_enemy(EnemyKind::Cultist, Vec2::new(120.0, 0.0));
+    let player = sim.player();
+    let max_health = sim.get::<PlayerHealth>(player).unwrap().max;
+
+    // It stands still and glows for a moment before its first shot.
+    sim.run_for(2.6);
+    assert!(sim.get::<AiRanged>(cultist).unwrap().winding_up());
+    assert_eq!(sim.get::<PlayerHealth>(player).unwrap().current, max_health);
+
+    sim.run_for(2.0);
+    assert_eq!(sim.get::<PlayerHealth>(player).unwrap().current, max_health - HEART);
+    assert_eq!(sim.app.world().resource::<RunStats>().damage_taken, 1.0);
+}
+
+#[test]
+fn snek_bites_for_half_a_heart() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    sim.spawn_enemy(EnemyKind::Snek, Vec2::new(20.0, 0.0));
+    let player = sim.player();
+    let max_health = sim.get::<PlayerHealth>(player).unwrap().max;
+
+    sim.run_for(1.0);
+    assert_eq!(sim.get::<PlayerHealth>(player).unwrap().current, max_health - 1);
+    assert_eq!(sim.app.world().resource::<RunStats>().damage_taken, 0.5);
+}
+
+#[test]
+fn boss_fight_locks_arena_and_drops_reward() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    sim.app.world_mut().resource_mut::<GameTimers>().game_time
+        .set_elapsed(Duration::from_secs_f32(60.0));
+    sim.step();
+
+    let world = sim.app.world_mut();
+    let boss = world.query_filtered::<Entity, With<Boss>>().single(world);
+    let arena_radius = world.query::<&Arena>().single(world).radius;
+
+    // Run for the edge of the arena.
+    sim.set_input(PlayerInput {
+        movement: Vec2::X,
+        ..default()
+    });
+    sim.run_for(4.0);
+    let player = sim.player();
+    let player_pos = sim.get::<Transform>(player).unwrap().translation.truncate();
+    assert!(player_pos.length() <= arena_radius + 1.0, "player left the arena at {}", player_pos);
+
+    // Knocking it below half health starts its second phase.
+    sim.app.world_mut().get_mut::<EnemyHealth>(boss).unwrap().current = 100.0;
+    sim.step();
+    assert_eq!(sim.get::<Boss>(boss).unwrap().phase(), 1);
+
+    sim.app.world_mut().get_mut::<EnemyHealth>(boss).unwrap().current = 0.0;
+    sim.step();
+    let world = sim.app.world_mut();
+    assert_eq!(world.query::<&Boss>().iter(world).count(), 0);
+    assert_eq!(world.query::<&Arena>().iter(world).count(), 0);
+    let rewards: Vec<PickupKind> = world.query::<&Pickup>().iter(world).map(|pickup| pickup.kind).collect();
+    assert_eq!(rewards, vec![PickupKind::Heart(2.0), PickupKind::HeartContainer, PickupKind::Xp(20)]);
+    assert_eq!(world.resource::<RunStats>().bosses_killed, 1);
+}
+
+#[test]
+fn spawner_blends_between_waves() {
+    let mut sim = Simulation::new(SEED);
+    let set_game_time = |sim: &mut Simulation, secs: f32| {
+        sim.app.world_mut().resource_mut::<GameTimers>().game_time
+            .set_elapsed(Duration::from_secs_f32(secs));
+        sim.step();
+    };
+    let spawner = |sim: &mut Simulation| {
+        let world = sim.app.world_mut();
+        let spawner = world.query::<&Spawner>().single(world);
+        (spawner.max_enemies, spawner.spawn_rate)
+    };
+
+    // Halfway between the first two waves.
+    set_game_time(&mut sim, 65.0);
+    let (max_enemies, spawn_rate) = spawner(&mut sim);
+    assert_eq!(max_enemies, 75);
+    assert!((spawn_rate - 0.75).abs() < 0.01, "spawn rate {}", spawn_rate);
+
+    // Difficulty set by hand sticks.
+    {
+        let world = sim.app.world_mut();
+        let mut spawner = world.query::<&mut Spawner>().single_mut(world);
+        spawner.follow_schedule = false;
+        spawner.set_difficulty(10, 2.0);
+    }
+    set_game_time(&mut sim, 200.0);
+    assert_eq!(spawner(&mut sim), (10, 2.0));
+}
+
+#[test]
+fn burst_is_telegraphed_before_spawning() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    // The snek burst comes in as a cluster of 8.
+    sim.app.world_mut().resource_mut::<GameTimers>().game_time
+        .set_elapsed(Duration::from_secs_f32(42.0));
+    sim.step();
+
+    let count_sneks = |sim: &mut Simulation| {
+        let world = sim.app.world_mut();
+        world.query::<&EnemyKind>().iter(world).filter(|kind| **kind == EnemyKind::Snek).count()
+    };
+    let count_warnings = |sim: &mut Simulation| {
+        let world = sim.app.world_mut();
+        world.query::<&SpawnWarning>().iter(world).count()
+    };
+    assert_eq!(count_warnings(&mut sim), 8);
+    assert_eq!(count_sneks(&mut sim), 0);
+
+    sim.run_for(1.0);
+    assert_eq!(count_warnings(&mut sim), 0);
+    assert_eq!(count_sneks(&mut sim), 8);
+}
+
+#[test]
+fn enemies_spawn_off_screen_and_keep_up() {
+    let mut sim = Simulation::new(SEED);
+    sim.run_for(1.5);
+    let half_size = sim.app.world().resource::<CameraView>().half_size;
+    let view = CameraView { half_size };
+    let spawn_range = CameraView { half_size: half_size + 30.0 };
+    let player = sim.player();
+    let player_pos = sim.get::<Transform>(player).unwrap().translation.truncate();
+    let world = sim.app.world_mut();
+    let spawned: Vec<Vec2> = world.query_filtered::<&Transform, With<SpawnWarning>>().iter(world)
+        .map(|transform| transform.translation.truncate() - player_pos)
+        .collect();
+    assert!(!spawned.is_empty());
+    for offset in spawned {
+        assert!(!view.contains(offset) && spawn_range.contains(offset), "enemy spawned at {}", offset);
+    }
+
+    // An enemy left far behind comes back around in front of the player.
+    sim.pause_spawner();
+    let enemy_count = sim.app.world().resource::<EnemyCount>().0;
+    let rat = sim.spawn_enemy(EnemyKind::Rat, player_pos - half_size * 10.0);
+    sim.step();
+    let offset = sim.get::<Transform>(rat).unwrap().translation.truncate() - player_pos;
+    assert!(!view.contains(offset) && spawn_range.contains(offset), "rat recycled to {}", offset);
+    assert_eq!(sim.app.world().resource::<EnemyCount>().0, enemy_count + 1);
+}
+
+#[test]
+fn heart_pickups_heal_and_expire() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let player = sim.player();
+    let max_health = sim.get::<PlayerHealth>(player).unwrap().max;
+    sim.app.world_mut().get_mut::<PlayerHealth>(player).unwrap().current = max_health - 2 * HEART;
+
+    // Close enough to get pulled in.
+    let near = sim.spawn_pickup(PickupKind::Heart(1.0), Vec2::new(30.0, 0.0));
+    let far = sim.spawn_pickup(PickupKind::Heart(1.0), Vec2::new(150.0, 0.0));
+    sim.run_for(1.0);
+    assert!(sim.get::<Pickup>(near).is_none());
+    assert_eq!(sim.get::<PlayerHealth>(player).unwrap().current, max_health - HEART);
+
+    // Left alone, it disappears.
+    assert!(sim.get::<Pickup>(far).is_some());
+    sim.run_for(15.0);
+    assert!(sim.get::<Pickup>(far).is_none());
+    assert_eq!(sim.get::<PlayerHealth>(player).unwrap().current, max_health - HEART);
+
+    // Heart containers add a full heart to the row.
+    sim.spawn_pickup(PickupKind::HeartContainer, Vec2::new(10.0, 0.0));
+    sim.run_for(0.5);
+    let health = sim.get::<PlayerHealth>(player).unwrap();
+    assert_eq!((health.current, health.max), (max_health, max_health + HEART));
+
+    // Healing isn't getting hit.
+    assert!(!sim.get::<PostHitInvulnerability>(player).unwrap().is_active());
+}
+
+#[test]
+fn upgrades_survive_re_rolls() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let player = sim.player();
+
+    // Enough for level 2.
+    sim.spawn_pickup(PickupKind::Xp(5), Vec2::new(10.0, 0.0));
+    sim.run_for(0.5);
+    assert_eq!(*sim.app.world().resource::<State<AppState>>().get(), AppState::LevelUp);
+    assert_eq!(sim.get::<Experience>(player).unwrap().level, 2);
+
+    // Pick one that changes weapon stats. This seed offers one at level 2.
+    let world = sim.app.world();
+    let config = world.resource::<Assets<UpgradesConfig>>().get(&world.resource::<GameAssets>().upgrades).unwrap();
+    let options = &world.resource::<LevelUpChoices>().options;
+    assert_eq!(options.len(), 3);
+    let option = options.iter()
+        .position(|&index| matches!(
+            config.upgrades[index].effect,
+            UpgradeEffect::FireRate(_) | UpgradeEffect::Ammo(_) | UpgradeEffect::Accuracy(_) | UpgradeEffect::Projectiles(_),
+        ))
+        .unwrap();
+    sim.choose_upgrade(option);
+    sim.step();
+    assert_eq!(*sim.app.world().resource::<State<AppState>>().get(), AppState::InGame);
+
+    let world = sim.app.world();
+    let weapons = world.resource::<Assets<WeaponsConfig>>().get(&world.resource::<GameAssets>().weapons).unwrap();
+    let pistol_stats = weapons.get(WeaponChoice::default()).stats.clone();
+    let upgrades = sim.get::<Upgrades>(player).unwrap();
+    assert_ne!(upgrades.weapon_stats(&pistol_stats), pistol_stats);
+    assert_eq!(sim.get::<Weapon>(player).unwrap().stats, upgrades.weapon_stats(&pistol_stats));
+
+    // Run out of ammo and re-roll.
+    let mut weapon = sim.app.world_mut().get_mut::<Weapon>(player).unwrap();
+    weapon.ammo = 0;
+    weapon.reloading = true;
+    weapon.cooldown = 0.0;
+    sim.step();
+
+    let world = sim.app.world();
+    let weapons = world.resource::<Assets<WeaponsConfig>>().get(&world.resource::<GameAssets>().weapons).unwrap();
+    let weapon = sim.get::<Weapon>(player).unwrap();
+    assert_ne!(weapon.equipped, WeaponChoice::default());
+    let upgrades = sim.get::<Upgrades>(player).unwrap();
+    assert_eq!(weapon.stats, upgrades.weapon_stats(&weapons.get(weapon.equipped).stats));
+}
+
+#[test]
+fn weapons_config_needs_a_weapon() {
+    assert!(ron::from_str::<WeaponsConfig>("(weapons: [])").is_err());
+}
+
+#[test]
+fn weapon_pool_rolls_a_different_allowed_weapon() {
+    let sim = Simulation::new(SEED);
+    let world = sim.app.world();
+    let mut weapons = WeaponsConfig {
+        weapons: world.resource::<Assets<WeaponsConfig>>().get(&world.resource::<GameAssets>().weapons).unwrap().weapons.clone(),
+    };
+    // More than six faces.
+    weapons.weapons.extend(weapons.weapons.clone());
+    let count = weapons.weapons.len();
+
+    let mut pool = WeaponPool::default();
+    let pistol = weapons.find("Pistol").unwrap();
+    let shotgun = weapons.find("Shotgun").unwrap();
+    assert!(pool.ban(pistol, &weapons));
+    pool.load(shotgun, 10.0);
+
+    let mut rng = fastrand::Rng::with_seed(SEED);
+    let mut rolled = vec![0; count];
+    let mut current = WeaponChoice(count - 1);
+    for _ in 0..2000 {
+        let next = pool.roll(current, &weapons, &mut rng);
+        assert_ne!(next, current);
+        rolled[next.0] += 1;
+        current = next;
+    }
+    assert_eq!(rolled[pistol.0], 0);
+    for (i, &times) in rolled.iter().enumerate() {
+        if i != pistol.0 {
+            assert!(times > 0, "weapon {} never came up", i);
+        }
+        if i != shotgun.0 {
+            assert!(rolled[shotgun.0] > times, "shotgun came up less than weapon {}", i);
+        }
+    }
+
+    // Banning everything but one weapon isn't allowed, since re-rolls have to change weapon.
+    let mut pool = WeaponPool::default();
+    let two = WeaponsConfig {
+        weapons: weapons.weapons[..2].to_vec(),
+    };
+    assert!(!pool.can_ban(WeaponChoice(0), &two));
+    assert!(!pool.ban(WeaponChoice(0), &two));
+    assert_eq!(pool.roll(WeaponChoice(0), &two, &mut rng), WeaponChoice(1));
+}
+
+#[test]
+fn held_weapon_swaps_and_keeps_its_ammo() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let player = sim.player();
+    sim.app.world_mut().get_mut::<Weapon>(player).unwrap().ammo = 10;
+
+    // Nothing held yet, so the pistol gets held and a new weapon rolled. Holding the button down
+    // only swaps once.
+    sim.set_input(PlayerInput {
+        next_weapon: true,
+        ..default()
+    });
+    sim.run_for(0.5);
+    let held = sim.get::<HeldWeapon>(player).unwrap().weapon.as_ref().unwrap();
+    assert_eq!((held.equipped, held.ammo), (WeaponChoice::default(), 10));
+    let rolled = sim.get::<Weapon>(player).unwrap().equipped;
+    assert_ne!(rolled, WeaponChoice::default());
+
+    // Swap back.
+    sim.app.world_mut().get_mut::<Weapon>(player).unwrap().ammo = 3;
+    sim.set_input(PlayerInput::default());
+    sim.step();
+    sim.set_input(PlayerInput {
+        prev_weapon: true,
+        ..default()
+    });
+    sim.step();
+    let weapon = sim.get::<Weapon>(player).unwrap();
+    assert_eq!((weapon.equipped, weapon.ammo), (WeaponChoice::default(), 10));
+    let held = sim.get::<HeldWeapon>(player).unwrap().weapon.as_ref().unwrap();
+    assert_eq!((held.equipped, held.ammo), (rolled, 3));
+}
+
+#[test]
+fn early_re_roll_trades_ammo_for_damage() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let player = sim.player();
+
+    // Re-rolling early throws away the magazine and takes as long as any other re-roll.
+    sim.set_input(PlayerInput {
+        reroll: true,
+        ..default()
+    });
+    sim.step();
+    let weapon = sim.get::<Weapon>(player).unwrap();
+    assert!(weapon.reloading);
+    assert_eq!(weapon.ammo, 0);
+
+    // Re-rolling again or shooting while it's going does nothing.
+    sim.set_input(PlayerInput::default());
+    sim.step();
+    sim.set_input(PlayerInput {
+        reroll: true,
+        shoot: true,
+        aim: Vec2::X,
+        ..default()
+    });
+    sim.run_for(1.0);
+    assert!(sim.get::<Weapon>(player).unwrap().reloading);
+    assert_eq!(sim.app.world().resource::<RunStats>().shots_fired, 0);
+
+    // A full magazine gives the biggest bonus.
+    sim.set_input(PlayerInput::default());
+    sim.run_for(1.5);
+    let weapon = sim.get::<Weapon>(player).unwrap();
+    assert_ne!(weapon.equipped, WeaponChoice::default());
+    assert!(!weapon.reloading);
+    assert_eq!(weapon.ammo, weapon.stats.max_ammo);
+    assert_eq!(weapon.bonus_damage, 0.5);
+    assert_eq!(sim.app.world().resource::<RunStats>().rerolls, 1);
+
+    // Half a magazine gives half the bonus.
+    let mut weapon = sim.app.world_mut().get_mut::<Weapon>(player).unwrap();
+    weapon.stats.max_ammo = 10;
+    weapon.ammo = 5;
+    sim.set_input(PlayerInput {
+        reroll: true,
+        ..default()
+    });
+    sim.step();
+    sim.set_input(PlayerInput::default());
+    sim.run_for(2.5);
+    assert_eq!(sim.get::<Weapon>(player).unwrap().bonus_damage, 0.25);
+    assert_eq!(sim.app.world().resource::<RunStats>().rerolls, 2);
+}
+
+/// Fires a single pistol shot along +x with the given projectile modifiers from upgrades, and
+/// lets it play out.
+fn fire_modified_shot(sim: &mut Simulation, modifiers: ProjectileModifiers) {
+    let player = sim.player();
+    sim.app.world_mut().get_mut::<Upgrades>(player).unwrap().projectile_modifiers = modifiers;
+    sim.set_input(PlayerInput {
+        aim: Vec2::X,
+        shoot: true,
+        ..default()
+    });
+    sim.step();
+    sim.set_input(PlayerInput::default());
+    sim.run_for(0.5);
+}
+
+fn damage_taken(sim: &Simulation, enemy: Entity) -> f32 {
+    let health = sim.get::<EnemyHealth>(enemy).unwrap();
+    health.max - health.current
+}
+
+#[test]
+fn piercing_shot_passes_through_rats() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let near = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(30.0, 0.0));
+    let far = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(60.0, 0.0));
+    fire_modified_shot(&mut sim, ProjectileModifiers {
+        pierce: 1,
+        ..default()
+    });
+    assert_eq!((damage_taken(&sim, near), damage_taken(&sim, far)), (4.0, 4.0));
+}
+
+#[test]
+fn ricochet_bounces_to_another_rat() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let first = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(40.0, 0.0));
+    let second = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(40.0, 40.0));
+    fire_modified_shot(&mut sim, ProjectileModifiers {
+        ricochet: 1,
+        ..default()
+    });
+    assert_eq!((damage_taken(&sim, first), damage_taken(&sim, second)), (4.0, 4.0));
+}
+
+#[test]
+fn chain_lightning_jumps_for_half_damage() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let first = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(40.0, 0.0));
+    let second = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(40.0, 40.0));
+    let out_of_range = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(40.0, -150.0));
+    fire_modified_shot(&mut sim, ProjectileModifiers {
+        chain: 1,
+        ..default()
+    });
+    assert_eq!(damage_taken(&sim, first), 4.0);
+    assert_eq!(damage_taken(&sim, second), 2.0);
+    assert_eq!(damage_taken(&sim, out_of_range), 0.0);
+}
+
+#[test]
+fn homing_shot_curves_into_rat() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    // Off to the side, where a straight shot would miss it.
+    let rat = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(60.0, 40.0));
+    fire_modified_shot(&mut sim, ProjectileModifiers {
+        homing: 360.0,
+        ..default()
+    });
+    assert_eq!(damage_taken(&sim, rat), 4.0);
+}
+
+#[test]
+fn split_shot_breaks_into_pieces() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let rat = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(40.0, 0.0));
+    let behind = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(70.0, 0.0));
+    fire_modified_shot(&mut sim, ProjectileModifiers {
+        split: 4,
+        ..default()
+    });
+    // One of the pieces carries on forward.
+    assert_eq!((damage_taken(&sim, rat), damage_taken(&sim, behind)), (4.0, 2.0));
+}
+
+#[test]
+fn split_pieces_keep_the_shot_crits() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let player = sim.player();
+    let stats = &mut sim.app.world_mut().get_mut::<Weapon>(player).unwrap().stats;
+    stats.crit_chance = 1.0;
+    stats.crit_multiplier = 2.0;
+    let rat = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(40.0, 0.0));
+    let behind = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(70.0, 0.0));
+
+    fire_modified_shot(&mut sim, ProjectileModifiers {
+        split: 4,
+        ..default()
+    });
+    assert_eq!((damage_taken(&sim, rat), damage_taken(&sim, behind)), (8.0, 4.0));
+}
+
+fn equip(sim: &mut Simulation, name: &str) {
+    let player = sim.player();
+    let world = sim.app.world_mut();
+    let handle = world.resource::<GameAssets>().weapons.clone();
+    let weapons = world.resource::<Assets<WeaponsConfig>>().get(&handle).unwrap();
+    let choice = weapons.find(name).unwrap();
+    let weapon = Weapon::new(choice, weapons, &Upgrades::default());
+    *world.get_mut::<Weapon>(player).unwrap() = weapon;
+}
+
+#[test]
+fn hit_box_only_hits_each_enemy_once() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let rat = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(60.0, 0.0));
+    let hit_box = sim.app.world_mut().spawn((
+        HitBoxBundle::new(Vec2::splat(8.0), Vec2::ZERO, 1.0, None, Group::NONE),
+        RigidBody::KinematicPositionBased,
+        ActiveEvents::COLLISION_EVENTS,
+    )).id();
+
+    // Sweep it back and forth over the rat a few times.
+    for _ in 0..3 {
+        let rat_pos = sim.get::<Transform>(rat).unwrap().translation;
+        sim.app.world_mut().get_mut::<Transform>(hit_box).unwrap().translation = rat_pos;
+        sim.run_for(0.1);
+        sim.app.world_mut().get_mut::<Transform>(hit_box).unwrap().translation = rat_pos + Vec3::Y * 100.0;
+        sim.run_for(0.1);
+    }
+    assert_eq!(damage_taken(&sim, rat), 1.0);
+}
+
+#[test]
+fn boomerang_hits_on_the_way_out_and_back() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    equip(&mut sim, "Boomerang");
+    // Far enough out that the rat is still in its way when it comes back.
+    let rat = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(100.0, 0.0));
+    sim.set_input(PlayerInput {
+        aim: Vec2::X,
+        shoot: true,
+        ..default()
+    });
+    sim.step();
+    sim.set_input(PlayerInput::default());
+    sim.run_for(2.0);
+    assert_eq!(damage_taken(&sim, rat), 6.0);
+}
+
+/// Hits an enemy once with a hit box that only applies a status effect.
+fn apply_status(sim: &mut Simulation, enemy: Entity, kind: StatusKind, duration: f32, strength: f32) {
+    let pos = sim.get::<Transform>(enemy).unwrap().translation;
+    let hit_box = sim.app.world_mut().spawn((
+        HitBoxBundle::new(Vec2::splat(8.0), Vec2::ZERO, 0.0, None, Group::NONE),
+        RigidBody::KinematicPositionBased,
+        ActiveEvents::COLLISION_EVENTS,
+    )).id();
+    let spec = StatusEffectSpec { kind, duration, strength };
+    sim.app.world_mut().entity_mut(hit_box).insert((HitSpec::new(0.0).with_statuses([spec]), Transform::from_translation(pos)));
+    sim.step();
+    sim.step();
+    sim.app.world_mut().despawn(hit_box);
+}
+
+#[test]
+fn burn_deals_damage_over_time_and_tints() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let rat = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(60.0, 0.0));
+    apply_status(&mut sim, rat, StatusKind::Burn, 1.0, 2.0);
+    assert!(sim.get::<StatusEffects>(rat).unwrap().has(StatusKind::Burn));
+    assert_ne!(sim.get::<Sprite>(rat).unwrap().color, Color::WHITE);
+
+    sim.run_for(1.5);
+    assert!((damage_taken(&sim, rat) - 2.0).abs() < 0.1, "took {}", damage_taken(&sim, rat));
+    assert!(!sim.get::<StatusEffects>(rat).unwrap().has(StatusKind::Burn));
+    assert_eq!(sim.get::<Sprite>(rat).unwrap().color, Color::WHITE);
+}
+
+#[test]
+fn burning_cultist_keeps_its_glow_and_tint() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let cultist = sim.spawn_enemy(EnemyKind::Cultist, Vec2::new(120.0, 0.0));
+    sim.run_for(2.6);
+    let ai = sim.get::<AiRanged>(cultist).unwrap();
+    assert!(ai.winding_up());
+    let glow = ai.telegraph_color().unwrap();
+
+    // Catching fire mid wind-up doesn't hide the shot coming.
+    apply_status(&mut sim, cultist, StatusKind::Burn, 10.0, 0.0);
+    assert_eq!(sim.get::<Sprite>(cultist).unwrap().color, glow);
+
+    // And shooting doesn't put out the tint.
+    sim.run_for(2.0);
+    assert!(!sim.get::<AiRanged>(cultist).unwrap().winding_up());
+    let color = sim.get::<Sprite>(cultist).unwrap().color;
+    assert_ne!(color, Color::WHITE);
+    assert_ne!(color, glow);
+}
+
+#[test]
+fn stunned_rat_stops_following() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let rat = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(60.0, 0.0));
+    apply_status(&mut sim, rat, StatusKind::Stun, 1.0, 0.0);
+
+    let stunned_at = sim.get::<Transform>(rat).unwrap().translation;
+    sim.run_for(0.5);
+    // It would have walked 25 pixels by now.
+    assert!(sim.get::<Transform>(rat).unwrap().translation.distance(stunned_at) < 2.0);
+
+    // Moves again once it wears off.
+    sim.run_for(1.0);
+    assert!(sim.get::<Transform>(rat).unwrap().translation.distance(stunned_at) > 10.0);
+}
+
+#[test]
+fn crit_deals_extra_damage_and_shows_a_number() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let player = sim.player();
+    let stats = &mut sim.app.world_mut().get_mut::<Weapon>(player).unwrap().stats;
+    stats.crit_chance = 1.0;
+    stats.crit_multiplier = 2.0;
+    let rat = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(40.0, 0.0));
+
+    sim.set_input(PlayerInput {
+        aim: Vec2::X,
+        shoot: true,
+        ..default()
+    });
+    sim.step();
+    sim.set_input(PlayerInput::default());
+    sim.run_for(0.2);
+    assert_eq!(damage_taken(&sim, rat), 8.0);
+    assert_eq!(sim.app.world().resource::<RunStats>().damage_dealt.get(&WeaponChoice(0)), Some(&8.0));
+
+    let numbers: Vec<(f32, bool)> = sim.app.world_mut().query::<&DamageNumber>()
+        .iter(sim.app.world())
+        .map(|number| (number.amount, number.crit))
+        .collect();
+    assert_eq!(numbers, vec![(8.0, true)]);
+
+    // They fade away.
+    sim.run_for(1.0);
+    assert_eq!(sim.app.world_mut().query::<&DamageNumber>().iter(sim.app.world()).count(), 0);
+}
+
+#[test]
+fn damage_numbers_can_be_turned_off() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    sim.app.world_mut().resource_mut::<Settings>().damage_numbers = false;
+    let rat = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(40.0, 0.0));
+
+    sim.set_input(PlayerInput {
+        aim: Vec2::X,
+        shoot: true,
+        ..default()
+    });
+    sim.step();
+    sim.set_input(PlayerInput::default());
+    sim.run_for(0.2);
+    assert_eq!(damage_taken(&sim, rat), 4.0);
+    assert_eq!(sim.app.world_mut().query::<&DamageNumber>().iter(sim.app.world()).count(), 0);
+}
+
+#[test]
+fn hit_rat_flashes_white() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    let rat = sim.spawn_enemy(EnemyKind::Rat, Vec2::new(20.0, 0.0));
+
+    sim.set_input(PlayerInput {
+        aim: Vec2::X,
+        shoot: true,
+        ..default()
+    });
+    let mut flashed = false;
+    for _ in 0..10 {
+        sim.step();
+        sim.set_input(PlayerInput::default());
+        flashed |= sim.get::<HitFlash>(rat).is_some() && sim.get::<Sprite>(rat).unwrap().color != Color::WHITE;
+    }
+    assert!(flashed);
+
+    // Back to its own color once the flash is over.
+    sim.run_for(0.5);
+    assert!(sim.get::<HitFlash>(rat).is_none());
+    assert_eq!(sim.get::<Sprite>(rat).unwrap().color, Color::WHITE);
+}
+
+#[test]
+fn player_hit_shakes_the_camera() {
+    let mut sim = Simulation::new(SEED);
+    sim.pause_spawner();
+    assert_eq!(sim.app.world().resource::<CameraShake>().trauma, 0.0);
+    sim.spawn_enemy(EnemyKind::Snek, Vec2::new(20.0, 0.0));
+
+    let mut shook = false;
+    for _ in 0..60 {
+        sim.step();
+        shook |= sim.app.world().resource::<CameraShake>().trauma > 0.0;
+    }
+    assert!(shook);
+
+    // It wears off.
+    sim.run_for(2.0);
+    assert_eq!(sim.app.world().resource::<CameraShake>().trauma, 0.0);
+}